@@ -5,4 +5,17 @@ use clap::Parser;
 pub(crate) struct Args {
     #[arg(short = 'c', value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
     pub config: Option<String>,
+    /// Report the problems of the config and exit, with a failure status if there are any
+    ///
+    /// Conditional sections are evaluated as if no output were connected, the X server is not asked.
+    #[arg(long)]
+    pub check_config: bool,
+    /// Print the built-in config, to start a config file from
+    #[arg(long)]
+    pub dump_default_config: bool,
+    /// Print the config in yaml, toml or ron, once parsed
+    ///
+    /// Conditional sections are evaluated as if no output were connected, the X server is not asked.
+    #[arg(long, value_name = "FORMAT")]
+    pub dump_config: Option<String>,
 }
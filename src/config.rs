@@ -1,11 +1,87 @@
-use std::{collections::HashMap, fs::File};
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    path::Path,
+    time::Duration,
+};
 
-use serde::{Deserialize, Deserializer, Serialize};
+use regex::Regex;
+use ron::extensions::Extensions;
+use serde::{
+    de::{DeserializeSeed, Error as _, MapAccess, SeqAccess, Visitor},
+    ser::Error as _,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
+use strum_macros::EnumString;
 use x11rb::protocol::xproto::KeyButMask;
 use xkbcommon::xkb::{Keysym, KEYSYM_NO_FLAGS};
 
-const CONFIG_FILE: &str = "config.ron";
+const CONFIG_FILE_STEM: &str = "config";
+/// Used when no config file is found.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.yaml");
+const DEFAULT_CONFIG_PATH: &str = "<built-in>";
+// Keys resolved while loading, before the config is built
+const INCLUDE_KEY: &str = "include";
+const VARS_KEY: &str = "vars";
+const CONDITIONAL_KEY: &str = "conditional";
+const WHEN_KEY: &str = "when";
+const MAX_INCLUDE_DEPTH: usize = 16;
 const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
+pub const DEFAULT_MODE: &str = "default";
+const DEFAULT_CHORD_TIMEOUT: u64 = 2000;
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// A problem found in a config file, with its line and column when they are known.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub path: String,
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => {
+                write!(f, "{}:{}:{}: {}", self.path, line, column, self.message)
+            }
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Formats a config file can be written in, told apart by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum ConfigFormat {
+    #[strum(serialize = "yaml", serialize = "yml")]
+    Yaml,
+    Toml,
+    Ron,
+}
+
+impl ConfigFormat {
+    const EXTENSIONS: [&'static str; 4] = ["yaml", "yml", "toml", "ron"];
+
+    /// The format of a file, YAML when the extension is not a known one.
+    pub fn from_path(path: &str) -> Self {
+        Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+            .unwrap_or(ConfigFormat::Yaml)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read {0}: {1}")]
+    Io(String, std::io::Error),
+
+    #[error("{0}")]
+    Parse(Diagnostic),
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum ModKey {
@@ -16,24 +92,431 @@ pub enum ModKey {
     Mod5,
 }
 
-#[derive(Debug, Deserialize, Serialize, Hash, Eq, PartialEq)]
-struct KeyCompound {
-    modifier_mask: u32,
-    keysym: Keysym,
+#[derive(Debug, Deserialize, Serialize, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct KeyCompound {
+    pub modifier_mask: u32,
+    pub keysym: Keysym,
+}
+
+impl std::fmt::Display for ButtonCompound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, mask) in MODIFIER_NAMES {
+            if self.modifier_mask & u32::from(mask) != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "button{}", self.button)?;
+        match self.context {
+            ButtonContext::Window => Ok(()),
+            ButtonContext::Border => write!(f, " on border"),
+            ButtonContext::Root => write!(f, " on root"),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyCompound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, mask) in MODIFIER_NAMES {
+            if self.modifier_mask & u32::from(mask) != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+        write!(f, "{}", xkbcommon::xkb::keysym_get_name(self.keysym))
+    }
+}
+
+pub type KeyMap = HashMap<KeyCompound, KeyBinding>;
+
+/// What a key of a key map leads to.
+#[derive(Debug, Serialize, Clone)]
+pub enum KeyBinding {
+    Command(KeyCommand),
+    /// The key starts a sequence such as `w, h`, the next key is looked up in this map.
+    Prefix(KeyMap),
+}
+
+/// A command bound to a key.
+/// Written either as the bare command or as `{ command: ..., release: true }`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(from = "KeyCommandDef", into = "KeyCommandDef")]
+pub struct KeyCommand {
+    pub command: String,
+    /// Run the command when the key is released instead of pressed,
+    /// for tools that need to grab the keyboard themselves.
+    pub release: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum KeyCommandDef {
+    Command(String),
+    Detailed {
+        command: String,
+        #[serde(default)]
+        release: bool,
+    },
+}
+
+impl From<KeyCommandDef> for KeyCommand {
+    fn from(def: KeyCommandDef) -> Self {
+        match def {
+            KeyCommandDef::Command(command) => Self {
+                command,
+                release: false,
+            },
+            KeyCommandDef::Detailed { command, release } => Self { command, release },
+        }
+    }
+}
+
+impl From<KeyCommand> for KeyCommandDef {
+    fn from(command: KeyCommand) -> Self {
+        if command.release {
+            KeyCommandDef::Detailed {
+                command: command.command,
+                release: true,
+            }
+        } else {
+            KeyCommandDef::Command(command.command)
+        }
+    }
+}
+
+/// A program started with the WM.
+/// Written either as the bare command or as `{ command: ..., workspace: ... }`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(from = "AutostartDef", into = "AutostartDef")]
+pub struct Autostart {
+    pub command: String,
+    /// Workspace its first window is placed on instead of the current one.
+    pub workspace: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum AutostartDef {
+    Command(String),
+    Detailed {
+        command: String,
+        workspace: Option<String>,
+    },
+}
+
+impl From<AutostartDef> for Autostart {
+    fn from(def: AutostartDef) -> Self {
+        match def {
+            AutostartDef::Command(command) => Self {
+                command,
+                workspace: None,
+            },
+            AutostartDef::Detailed { command, workspace } => Self { command, workspace },
+        }
+    }
+}
+
+impl From<Autostart> for AutostartDef {
+    fn from(autostart: Autostart) -> Self {
+        match autostart.workspace {
+            Some(workspace) => AutostartDef::Detailed {
+                command: autostart.command,
+                workspace: Some(workspace),
+            },
+            None => AutostartDef::Command(autostart.command),
+        }
+    }
+}
+
+/// Where a mouse binding applies.
+#[derive(Debug, Deserialize, Serialize, Hash, Eq, PartialEq, Clone, Copy)]
+pub enum ButtonContext {
+    /// The client window, the default
+    Window,
+    /// The border drawn around the client by its frame
+    Border,
+    /// The desktop, where no window is
+    Root,
+}
+
+#[derive(Debug, Deserialize, Serialize, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct ButtonCompound {
+    pub modifier_mask: u32,
+    pub button: u8,
+    pub context: ButtonContext,
+}
+
+pub type MouseMap = HashMap<ButtonCompound, String>;
+
+/// How the pointer moves the focus around.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusPolicy {
+    /// Only clicking a window focuses it
+    Click,
+    /// Entering a window focuses it, leaving every window for the desktop unfocuses
+    FollowsMouse,
+    /// Entering a window focuses it, the desktop keeps the last focus
+    #[default]
+    Sloppy,
+}
+
+/// What a window asking to be activated through `_NET_ACTIVE_WINDOW` gets.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusOnWindowActivation {
+    /// Focus on the visible workspace, marked urgent on the others
+    #[default]
+    Smart,
+    /// Only marked urgent
+    Urgent,
+    /// Always focus, switching to its workspace
+    Focus,
+    /// Ignore the request
+    None,
+}
+
+/// A regular expression, written as a string.
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Regex::new(&s).map(Pattern).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+/// The properties of a window that rules are matched against.
+#[derive(Debug, Default)]
+pub struct WindowProperties {
+    pub class: String,
+    pub instance: String,
+    pub role: String,
+    /// Lowercased and without the `_NET_WM_WINDOW_TYPE_` prefix, such as `dialog`
+    pub window_type: String,
+    pub title: String,
+}
+
+/// Every given pattern has to match for a rule to apply.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct WindowCriteria {
+    class: Option<Pattern>,
+    instance: Option<Pattern>,
+    role: Option<Pattern>,
+    window_type: Option<Pattern>,
+    title: Option<Pattern>,
+}
+
+impl WindowCriteria {
+    pub fn matches(&self, properties: &WindowProperties) -> bool {
+        [
+            (&self.class, &properties.class),
+            (&self.instance, &properties.instance),
+            (&self.role, &properties.role),
+            (&self.window_type, &properties.window_type),
+            (&self.title, &properties.title),
+        ]
+        .into_iter()
+        .all(|(pattern, value)| pattern.as_ref().is_none_or(|p| p.is_match(value)))
+    }
+
+    /// Parse criteria given to a command, such as `class=^URxvt$ title=notes`.
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut criteria = Self::default();
+        for arg in args {
+            let (key, pattern) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=pattern, got {}", arg))?;
+            let pattern = Regex::new(pattern)
+                .map(Pattern)
+                .map_err(|e| e.to_string())?;
+            let field = match key {
+                "class" => &mut criteria.class,
+                "instance" => &mut criteria.instance,
+                "role" => &mut criteria.role,
+                "window_type" => &mut criteria.window_type,
+                "title" => &mut criteria.title,
+                _ => return Err(format!("unknown criterion {}", key)),
+            };
+            *field = Some(pattern);
+        }
+        Ok(criteria)
+    }
+
+    /// Rules depending on the title are checked again whenever it changes.
+    pub fn has_title(&self) -> bool {
+        self.title.is_some()
+    }
+}
+
+/// What the conditional sections of a config are evaluated against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigContext {
+    pub hostname: String,
+    /// Names of the connected outputs, such as `HDMI-1`
+    pub outputs: Vec<String>,
+}
+
+impl ConfigContext {
+    /// The context of this host, with the given outputs connected.
+    pub fn new(outputs: Vec<String>) -> Self {
+        let hostname = nix::unistd::gethostname()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self { hostname, outputs }
+    }
+}
+
+/// Every given condition has to hold for a conditional section to apply.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Condition {
+    hostname: Option<Pattern>,
+    /// Number of connected outputs
+    outputs: Option<usize>,
+    /// Name of one of the connected outputs
+    output: Option<Pattern>,
+}
+
+impl Condition {
+    fn holds(&self, context: &ConfigContext) -> bool {
+        self.hostname
+            .as_ref()
+            .is_none_or(|p| p.is_match(&context.hostname))
+            && self.outputs.is_none_or(|n| n == context.outputs.len())
+            && self
+                .output
+                .as_ref()
+                .is_none_or(|p| context.outputs.iter().any(|o| p.is_match(o)))
+    }
+
+    fn depends_on_outputs(&self) -> bool {
+        self.outputs.is_some() || self.output.is_some()
+    }
+}
+
+/// Per application behaviour, applied when a window is managed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WindowRule {
+    #[serde(rename = "match")]
+    pub criteria: WindowCriteria,
+    /// Workspace the window is placed on instead of the current one.
+    pub workspace: Option<String>,
+    /// Commands run with the window focused.
+    #[serde(default)]
+    pub actions: Vec<String>,
+}
+
+/// A window toggled by name, spawned the first time it is asked for.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NamedScratchpad {
+    /// Identifies the window, usually by the class or instance the command gives it.
+    #[serde(rename = "match")]
+    pub criteria: WindowCriteria,
+    pub command: String,
+    /// Width and height of the window, half of the workspace when not given.
+    pub size: Option<(u32, u32)>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
-    #[serde(deserialize_with = "deserialize_mod_key")]
+    #[serde(
+        deserialize_with = "deserialize_mod_key",
+        serialize_with = "serialize_mod_key"
+    )]
     pub mod_key: u32,
     modes: HashMap<String, ConfigMode>,
     custom_commands: Option<HashMap<String, String>>,
+    /// Abort a pending key sequence after this many milliseconds.
+    chord_timeout: Option<u64>,
+    /// Unlike key maps, mouse bindings are not prefixed with the mod key.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_mouse_bindings",
+        serialize_with = "serialize_mouse_bindings"
+    )]
+    mouse_bindings: Option<MouseMap>,
+    #[serde(default)]
+    focus_policy: FocusPolicy,
+    /// Move the pointer to the middle of a window focused from the keyboard.
+    #[serde(default)]
+    mouse_warping: bool,
+    /// Directional focus continues on the other side once it reaches an edge.
+    #[serde(default = "default_focus_wrapping")]
+    focus_wrapping: bool,
+    #[serde(default)]
+    focus_on_window_activation: FocusOnWindowActivation,
+    #[serde(default)]
+    rules: Vec<WindowRule>,
+    #[serde(default)]
+    scratchpads: HashMap<String, NamedScratchpad>,
+    /// Shell running spawned commands as `<shell> -c <command>`, /bin/sh by default.
+    shell: Option<String>,
+    /// Programs started once per X session, not again when the WM restarts.
+    #[serde(default)]
+    autostart: Vec<Autostart>,
+    /// Programs started whenever the WM starts or reloads its config.
+    #[serde(default)]
+    autostart_always: Vec<Autostart>,
+    /// Reload the config whenever its file changes.
+    #[serde(default)]
+    auto_reload: bool,
+    // Where the config was read from
+    #[serde(skip)]
+    path: String,
+    // Where each of its values is written, to locate diagnostics
+    #[serde(skip)]
+    origin: Origin,
+    // The config file and the files it includes
+    #[serde(skip)]
+    files: Vec<String>,
+    // Whether a conditional section depends on the connected outputs
+    #[serde(skip)]
+    output_dependent: bool,
+}
+
+fn default_focus_wrapping() -> bool {
+    true
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ConfigMode {
-    #[serde(deserialize_with = "deserialize_key_maps")]
-    key_maps: Option<HashMap<Keysym, HashMap<u32, String>>>,
+    #[serde(
+        deserialize_with = "deserialize_key_maps",
+        serialize_with = "serialize_key_maps"
+    )]
+    key_maps: Option<KeyMap>,
+    /// Key that switches back to the default mode.
+    /// Unlike key maps, it is not prefixed with the mod key.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_key_compound",
+        serialize_with = "serialize_key_compound",
+        skip_serializing_if = "Option::is_none"
+    )]
+    escape: Option<KeyCompound>,
+    /// Return to the default mode after this many milliseconds without a key press.
+    timeout: Option<u64>,
+    /// Bind the keys of this mode without the mod key, as in a resize mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    bare_keys: bool,
 }
 
 fn deserialize_mod_key<'de, D>(deserializer: D) -> Result<u32, D::Error>
@@ -52,35 +535,309 @@ where
     .into())
 }
 
-fn deserialize_key_maps<'de, D>(
-    deserializer: D,
-) -> Result<Option<HashMap<Keysym, HashMap<u32, String>>>, D::Error>
+fn serialize_mod_key<S>(mod_key: &u32, serializer: S) -> Result<S::Ok, S::Error>
 where
-    D: Deserializer<'de>,
+    S: Serializer,
 {
-    let s: HashMap<String, String> = Deserialize::deserialize(deserializer)?;
-    let mut key_maps = HashMap::new();
-    for (key, handler_name) in s {
-        let (mod_mask, key_sym): (u32, u32) = key.split('+').fold((0, 0), |(mod_mask, _), k| {
-            let k = k.trim();
-            let mut key_sym = 0;
-            let mut current_mod_mask = mod_mask;
+    let mod_key = [
+        (KeyButMask::MOD1, ModKey::Mod1),
+        (KeyButMask::MOD2, ModKey::Mod2),
+        (KeyButMask::MOD3, ModKey::Mod3),
+        (KeyButMask::MOD4, ModKey::Mod4),
+        (KeyButMask::MOD5, ModKey::Mod5),
+    ]
+    .into_iter()
+    .find(|&(mask, _)| u32::from(mask) == *mod_key)
+    .map(|(_, mod_key)| mod_key)
+    .ok_or_else(|| S::Error::custom(format!("invalid mod key mask {}", mod_key)))?;
+    mod_key.serialize(serializer)
+}
 
-            if let Some(m) = get_modifier_mask(k) {
-                current_mod_mask |= <KeyButMask as Into<u32>>::into(m)
-            } else {
-                key_sym = xkbcommon::xkb::keysym_from_name(k, KEYSYM_NO_FLAGS);
+/// Key maps are written back as the key strings they were read from,
+/// with sequences flattened to `w, h`.
+fn serialize_key_maps<S>(key_maps: &Option<KeyMap>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut flat = BTreeMap::new();
+    if let Some(key_maps) = key_maps {
+        flatten_key_map(key_maps, None, &mut flat);
+    }
+    flat.serialize(serializer)
+}
+
+fn flatten_key_map<'a>(
+    key_map: &'a KeyMap,
+    prefix: Option<&str>,
+    flat: &mut BTreeMap<String, &'a KeyCommand>,
+) {
+    for (key, binding) in key_map {
+        let sequence = match prefix {
+            Some(prefix) => format!("{}, {}", prefix, key),
+            None => key.to_string(),
+        };
+        match binding {
+            KeyBinding::Command(command) => {
+                flat.insert(sequence, command);
             }
-            (current_mod_mask, key_sym)
-        });
-        let entry = key_maps.entry(key_sym).or_insert(HashMap::new());
+            KeyBinding::Prefix(next) => flatten_key_map(next, Some(&sequence), flat),
+        }
+    }
+}
+
+fn serialize_mouse_bindings<S>(
+    mouse_bindings: &Option<MouseMap>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    mouse_bindings
+        .as_ref()
+        .map(|m| {
+            m.iter()
+                .map(|(button, command)| (button.to_string(), command))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .serialize(serializer)
+}
 
-        entry.insert(mod_mask, handler_name);
+fn serialize_key_compound<S>(key: &Option<KeyCompound>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    key.map(|k| k.to_string()).serialize(serializer)
+}
+
+/// Key strings may hold a comma separated sequence such as `w, h`.
+/// Only the first key of a sequence is prefixed with the mod key.
+fn deserialize_key_maps<'de, D>(deserializer: D) -> Result<Option<KeyMap>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(KeyMapVisitor).map(Some)
+}
+
+struct KeyMapVisitor;
+
+impl<'de> Visitor<'de> for KeyMapVisitor {
+    type Value = KeyMap;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a map of keys to commands")
     }
 
-    Ok(Some(key_maps))
+    fn visit_map<A>(self, mut map: A) -> Result<KeyMap, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut key_map = HashMap::new();
+        while let Some((key, sequence)) = map.next_key_seed(ParsedKey(parse_key_sequence))? {
+            let insert = |command| {
+                insert_key_sequence(&mut key_map, &sequence, command)
+                    .map_err(|conflict| format!("{} conflicts with {}", key, conflict))
+            };
+            map.next_value_seed(InsertedValue(insert, PhantomData))?;
+        }
+        Ok(key_map)
+    }
 }
 
+/// Reads a map key and parses it. A key that does not parse fails while it is read,
+/// so that its path in the config leads to it.
+struct ParsedKey<F>(F);
+
+impl<'de, T, F> DeserializeSeed<'de> for ParsedKey<F>
+where
+    F: FnOnce(&str) -> Result<T, String>,
+{
+    type Value = (String, T);
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let key = String::deserialize(deserializer)?;
+        let parsed = (self.0)(&key).map_err(D::Error::custom)?;
+        Ok((key, parsed))
+    }
+}
+
+/// Reads a map value and inserts it, failing at the path of its key when it cannot be.
+struct InsertedValue<T, F>(F, PhantomData<T>);
+
+impl<'de, T, F> DeserializeSeed<'de> for InsertedValue<T, F>
+where
+    T: Deserialize<'de>,
+    F: FnOnce(T) -> Result<(), String>,
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = T::deserialize(deserializer)?;
+        (self.0)(value).map_err(D::Error::custom)
+    }
+}
+
+fn parse_key_sequence(keys: &str) -> Result<Vec<KeyCompound>, String> {
+    keys.split(',').map(parse_key_compound).collect()
+}
+
+/// Insert a binding in the prefix tree of a key map.
+/// A key cannot both run a command and start a sequence, the conflicting key is returned.
+fn insert_key_sequence(
+    key_map: &mut KeyMap,
+    sequence: &[KeyCompound],
+    command: KeyCommand,
+) -> Result<(), KeyCompound> {
+    let (&key, rest) = sequence.split_first().expect("key sequence is empty");
+    if rest.is_empty() {
+        if key_map.contains_key(&key) {
+            return Err(key);
+        }
+        key_map.insert(key, KeyBinding::Command(command));
+        return Ok(());
+    }
+
+    match key_map
+        .entry(key)
+        .or_insert_with(|| KeyBinding::Prefix(HashMap::new()))
+    {
+        KeyBinding::Prefix(next) => insert_key_sequence(next, rest, command),
+        KeyBinding::Command(_) => Err(key),
+    }
+}
+
+/// Button strings look like `super+button1`, optionally followed by
+/// `on window`, `on border` or `on root`.
+fn deserialize_mouse_bindings<'de, D>(deserializer: D) -> Result<Option<MouseMap>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_option(MouseMapVisitor)
+}
+
+struct MouseMapVisitor;
+
+impl<'de> Visitor<'de> for MouseMapVisitor {
+    type Value = Option<MouseMap>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a map of buttons to commands")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut mouse_map = HashMap::new();
+        while let Some((button, compound)) = map.next_key_seed(ParsedKey(parse_button_compound))? {
+            let insert = |command| match mouse_map.insert(compound, command) {
+                Some(_) => Err(format!("{} is bound twice", button)),
+                None => Ok(()),
+            };
+            map.next_value_seed(InsertedValue(insert, PhantomData))?;
+        }
+        Ok(Some(mouse_map))
+    }
+}
+
+fn parse_button_compound(button: &str) -> Result<ButtonCompound, String> {
+    let (button_str, context) = match button.split_once(" on ") {
+        Some((b, context)) => (b, context.trim()),
+        None => (button, "window"),
+    };
+    let context = match context {
+        "window" => ButtonContext::Window,
+        "border" => ButtonContext::Border,
+        "root" => ButtonContext::Root,
+        _ => return Err(format!("unknown button context {} in {}", context, button)),
+    };
+
+    let mut modifier_mask = 0;
+    let mut button_index = None;
+    for part in button_str.split('+').map(str::trim) {
+        if let Some(m) = get_modifier_mask(part) {
+            modifier_mask |= u32::from(m);
+        } else if let Some(n) = part
+            .strip_prefix("button")
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|&n| n > 0)
+        {
+            button_index = Some(n);
+        } else {
+            return Err(format!("unknown modifier or button {} in {}", part, button));
+        }
+    }
+    let Some(button_index) = button_index else {
+        return Err(format!("no button in {}", button));
+    };
+
+    Ok(ButtonCompound {
+        modifier_mask,
+        button: button_index,
+        context,
+    })
+}
+
+fn deserialize_key_compound<'de, D>(deserializer: D) -> Result<Option<KeyCompound>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    s.map(|s| parse_key_compound(&s))
+        .transpose()
+        .map_err(D::Error::custom)
+}
+
+/// Parse a key string such as `shift+h` into its modifier mask and keysym.
+/// Every part but the last has to be a modifier, the last one a keysym name.
+fn parse_key_compound(key: &str) -> Result<KeyCompound, String> {
+    let parts: Vec<&str> = key.split('+').map(str::trim).collect();
+    let (&key_name, modifiers) = parts.split_last().expect("split yields at least one part");
+    let mut modifier_mask = 0;
+    for &modifier in modifiers {
+        let Some(m) = get_modifier_mask(modifier) else {
+            return Err(format!("unknown modifier {} in {}", modifier, key.trim()));
+        };
+        modifier_mask |= u32::from(m);
+    }
+    let keysym = xkbcommon::xkb::keysym_from_name(key_name, KEYSYM_NO_FLAGS);
+    if keysym == xkbcommon::xkb::KEY_NoSymbol {
+        return Err(format!("unknown key {} in {}", key_name, key.trim()));
+    }
+
+    Ok(KeyCompound {
+        modifier_mask,
+        keysym,
+    })
+}
+
+const MODIFIER_NAMES: [(&str, KeyButMask); 4] = [
+    ("ctrl", KeyButMask::CONTROL),
+    ("alt", KeyButMask::MOD1),
+    ("shift", KeyButMask::SHIFT),
+    ("super", KeyButMask::MOD4),
+];
+
 fn get_modifier_mask(key: &str) -> Option<KeyButMask> {
     match key {
         "alt" | "Alt" => Some(KeyButMask::MOD1),
@@ -92,10 +849,89 @@ fn get_modifier_mask(key: &str) -> Option<KeyButMask> {
 }
 
 impl Config {
-    pub fn get_key_maps(&self, mode: &str) -> Option<&HashMap<Keysym, HashMap<u32, String>>> {
+    pub fn get_key_maps(&self, mode: &str) -> Option<&KeyMap> {
         self.modes.get(mode).and_then(|m| m.key_maps.as_ref())
     }
 
+    pub fn has_mode(&self, mode: &str) -> bool {
+        self.modes.contains_key(mode)
+    }
+
+    /// Get the key that leaves `mode`.
+    /// Every mode other than the default one falls back to a bare `Escape`.
+    pub fn get_escape_key(&self, mode: &str) -> Option<KeyCompound> {
+        let config_mode = self.modes.get(mode)?;
+        config_mode.escape.or_else(|| {
+            (mode != DEFAULT_MODE).then_some(KeyCompound {
+                modifier_mask: 0,
+                keysym: xkbcommon::xkb::KEY_Escape,
+            })
+        })
+    }
+
+    /// The modifiers every key map binding of `mode` is prefixed with.
+    pub fn get_mode_mask(&self, mode: &str) -> u32 {
+        match self.modes.get(mode) {
+            Some(m) if m.bare_keys => 0,
+            _ => self.get_mod_mask(),
+        }
+    }
+
+    pub fn get_mode_timeout(&self, mode: &str) -> Option<Duration> {
+        self.modes
+            .get(mode)
+            .and_then(|m| m.timeout)
+            .map(Duration::from_millis)
+    }
+
+    pub fn get_mouse_bindings(&self) -> Option<&MouseMap> {
+        self.mouse_bindings.as_ref()
+    }
+
+    pub fn get_focus_policy(&self) -> FocusPolicy {
+        self.focus_policy
+    }
+
+    pub fn get_mouse_warping(&self) -> bool {
+        self.mouse_warping
+    }
+
+    pub fn get_focus_wrapping(&self) -> bool {
+        self.focus_wrapping
+    }
+
+    pub fn get_focus_on_window_activation(&self) -> FocusOnWindowActivation {
+        self.focus_on_window_activation
+    }
+
+    pub fn get_rules(&self) -> &[WindowRule] {
+        &self.rules
+    }
+
+    pub fn get_scratchpad(&self, name: &str) -> Option<&NamedScratchpad> {
+        self.scratchpads.get(name)
+    }
+
+    pub fn get_shell(&self) -> &str {
+        self.shell.as_deref().unwrap_or(DEFAULT_SHELL)
+    }
+
+    pub fn get_autostart(&self) -> &[Autostart] {
+        &self.autostart
+    }
+
+    pub fn get_autostart_always(&self) -> &[Autostart] {
+        &self.autostart_always
+    }
+
+    pub fn get_auto_reload(&self) -> bool {
+        self.auto_reload
+    }
+
+    pub fn get_chord_timeout(&self) -> Duration {
+        Duration::from_millis(self.chord_timeout.unwrap_or(DEFAULT_CHORD_TIMEOUT))
+    }
+
     pub(crate) fn get_mod_mask(&self) -> u32 {
         return self.mod_key;
     }
@@ -103,23 +939,1142 @@ impl Config {
     pub(crate) fn get_custom_commands(&self) -> Option<&HashMap<String, String>> {
         return self.custom_commands.as_ref();
     }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// The config file and the files it includes, none for the built-in config.
+    pub fn get_files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Whether the config has to be loaded again when the connected outputs change.
+    pub fn depends_on_outputs(&self) -> bool {
+        self.output_dependent
+    }
+
+    /// Write the config in any format, it reads back to the same config.
+    pub fn dump(&self, format: ConfigFormat) -> Result<String, String> {
+        match format {
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+            ConfigFormat::Ron => ron_options()
+                .to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Find the problems the parser cannot see, given the names of the commands
+    /// the WM knows: unknown commands and references to undefined modes.
+    pub fn check(&self, commands: &[String]) -> Vec<Diagnostic> {
+        // the commands, what binds them and their path in the config
+        let mut bound: Vec<(&str, String, Vec<String>)> = Vec::new();
+        for (name, mode) in &self.modes {
+            let Some(key_map) = &mode.key_maps else {
+                continue;
+            };
+            let path = ["modes", name, "key_maps"].map(String::from);
+            let mut sequences = Vec::new();
+            collect_key_commands(key_map, &mut Vec::new(), &mut sequences);
+            for (sequence, command) in sequences {
+                // the bound sequence may be written in several ways, such as `w,h`
+                let key = self.written_key(&path, |k| {
+                    parse_key_sequence(k).is_ok_and(|s| s == sequence)
+                });
+                let path = path.iter().chain(key).cloned().collect();
+                bound.push((&command.command, format!("mode {}", name), path));
+            }
+        }
+        for (button, command) in self.mouse_bindings.iter().flatten() {
+            let path = ["mouse_bindings".to_string()];
+            let key = self.written_key(&path, |b| {
+                parse_button_compound(b).is_ok_and(|b| b == *button)
+            });
+            let path = path.iter().chain(key).cloned().collect();
+            bound.push((command, "mouse bindings".into(), path));
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
+            for (action_index, action) in rule.actions.iter().enumerate() {
+                let path = [
+                    "rules",
+                    &index.to_string(),
+                    "actions",
+                    &action_index.to_string(),
+                ];
+                bound.push((action, "rules".into(), path.map(String::from).into()));
+            }
+        }
+
+        let mut problems = Vec::new();
+        for (command, context, path) in bound {
+            let mut words = command.split_whitespace();
+            let message = match words.next() {
+                None => format!("empty command in {}", context),
+                Some(name) if !commands.iter().any(|c| c == name) => {
+                    format!("unknown command {} in {}", name, context)
+                }
+                Some("mode") => match words.next().filter(|m| !self.modes.contains_key(*m)) {
+                    Some(mode) => format!("undefined mode {} in {}", mode, context),
+                    None => continue,
+                },
+                Some(_) => continue,
+            };
+            let origin = self.origin.at(&path);
+            problems.push((origin.file.clone(), origin.location, message));
+        }
+
+        problems.sort();
+        problems.dedup();
+        problems
+            .into_iter()
+            .map(|(path, location, message)| Diagnostic {
+                path,
+                location,
+                message,
+            })
+            .collect()
+    }
+
+    /// The key of the mapping at `path` matching `parses_to`, as written in the config.
+    fn written_key(&self, path: &[String], parses_to: impl Fn(&str) -> bool) -> Option<&String> {
+        let written = self.origin.get(path)?;
+        written.children.keys().find(|k| parses_to(k))
+    }
+}
+
+/// Commands of a key map and of the sequences it starts, with the keys leading to them.
+fn collect_key_commands<'a>(
+    key_map: &'a KeyMap,
+    prefix: &mut Vec<KeyCompound>,
+    commands: &mut Vec<(Vec<KeyCompound>, &'a KeyCommand)>,
+) {
+    for (key, binding) in key_map {
+        prefix.push(*key);
+        match binding {
+            KeyBinding::Command(command) => commands.push((prefix.clone(), command)),
+            KeyBinding::Prefix(next) => collect_key_commands(next, prefix, commands),
+        }
+        prefix.pop();
+    }
+}
+
+/// Line and column, counted from 1, of a byte index in `source`.
+fn location_of(source: &str, index: usize) -> (usize, usize) {
+    let before = &source[..index];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// Load the config from `path`, or from the first file found in the search paths.
+/// The built-in config is used when there is none. Conditional sections are
+/// applied when they hold in `context`.
+pub fn load_config(path: Option<&str>, context: &ConfigContext) -> Result<Config, ConfigError> {
+    let config = match find_config_path(path) {
+        Some(path) => {
+            let source =
+                std::fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+            parse_config(path, source, context)?
+        }
+        None => parse_config(DEFAULT_CONFIG_PATH.into(), DEFAULT_CONFIG.into(), context)?,
+    };
+    // escape keys and mode timeouts fall back to the default mode
+    if !config.has_mode(DEFAULT_MODE) {
+        let message = format!("no {} mode", DEFAULT_MODE);
+        return Err(config.origin.at(&["modes"]).error(message));
+    }
+    Ok(config)
+}
+
+fn parse_config(
+    path: String,
+    source: String,
+    context: &ConfigContext,
+) -> Result<Config, ConfigError> {
+    // RON writes enum variants as bare identifiers, which a document read without
+    // the types of the config cannot keep, so it is read directly, without includes,
+    // variables or conditional sections
+    if ConfigFormat::from_path(&path) == ConfigFormat::Ron {
+        let ron_error = |e: ron::error::SpannedError| {
+            ConfigError::Parse(Diagnostic {
+                path: path.clone(),
+                location: Some((e.position.line, e.position.col)),
+                message: e.code.to_string(),
+            })
+        };
+        // serde would skip them silently
+        if let ron::Value::Map(document) = ron_options().from_str(&source).map_err(ron_error)? {
+            for key in [INCLUDE_KEY, VARS_KEY, CONDITIONAL_KEY] {
+                if document
+                    .iter()
+                    .any(|(k, _)| *k == ron::Value::String(key.into()))
+                {
+                    let message = format!("{} is not supported in RON files", key);
+                    return Err(Origin::new(&path).error(message));
+                }
+            }
+        }
+        let mut config: Config = ron_options().from_str(&source).map_err(ron_error)?;
+        config.files = vec![path.clone()];
+        config.origin = Origin::new(&path);
+        config.path = path;
+        return Ok(config);
+    }
+
+    let mut files = Vec::new();
+    if path != DEFAULT_CONFIG_PATH {
+        files.push(path.clone());
+    }
+    let (mut document, mut origin) =
+        load_document(&path, &source, &mut vec![path.clone()], &mut files)?;
+    let output_dependent = apply_conditional_sections(&mut document, &mut origin, context)?;
+
+    // the merged document has no positions, errors past this point are placed
+    // where the faulty value was written by its path in the config
+    let vars = document
+        .as_object_mut()
+        .and_then(|d| d.remove(VARS_KEY))
+        .unwrap_or_else(|| Value::Object(Map::new()));
+    let vars_origin = origin.take(VARS_KEY);
+    let vars: HashMap<String, String> = serde_path_to_error::deserialize(vars).map_err(|e| {
+        let message = format!("{}: {}", VARS_KEY, e);
+        vars_origin.at_path(e.path()).error(message)
+    })?;
+    let vars = vars
+        .into_iter()
+        .map(|(name, value)| (name, expand_string(&value, &HashMap::new())))
+        .collect();
+    expand_value(&mut document, &mut origin, &vars);
+
+    let mut config: Config = serde_path_to_error::deserialize(document).map_err(|e| {
+        let message = format!("{}: {}", e.path(), e.inner());
+        origin.at_path(e.path()).error(message)
+    })?;
+    config.path = path;
+    config.origin = origin;
+    config.files = files;
+    config.output_dependent = output_dependent;
+    Ok(config)
+}
+
+/// Merge the conditional sections that hold in `context` into the document,
+/// in order. Returns whether any of them depends on the connected outputs.
+fn apply_conditional_sections(
+    document: &mut Value,
+    origin: &mut Origin,
+    context: &ConfigContext,
+) -> Result<bool, ConfigError> {
+    let Some(sections) = document
+        .as_object_mut()
+        .and_then(|d| d.remove(CONDITIONAL_KEY))
+    else {
+        return Ok(false);
+    };
+    let mut sections_origin = origin.take(CONDITIONAL_KEY);
+    let Value::Array(sections) = sections else {
+        let message = format!("{} has to be a list of sections", CONDITIONAL_KEY);
+        return Err(sections_origin.error(message));
+    };
+
+    let mut output_dependent = false;
+    for (index, mut section) in sections.into_iter().enumerate() {
+        let mut section_origin = sections_origin.take(&index.to_string());
+        let condition = section
+            .as_object_mut()
+            .and_then(|s| s.remove(WHEN_KEY))
+            .ok_or_else(|| {
+                section_origin.error(format!(
+                    "{}[{}]: sections need a {} key",
+                    CONDITIONAL_KEY, index, WHEN_KEY
+                ))
+            })?;
+        let condition_origin = section_origin.take(WHEN_KEY);
+        let condition: Condition = serde_json::from_value(condition).map_err(|e| {
+            condition_origin.error(format!(
+                "{}[{}].{}: {}",
+                CONDITIONAL_KEY, index, WHEN_KEY, e
+            ))
+        })?;
+        output_dependent |= condition.depends_on_outputs();
+        if condition.holds(context) {
+            merge(document, origin, section, section_origin, 0);
+        }
+    }
+    Ok(output_dependent)
 }
 
-pub fn load_config(path: Option<&str>) -> Result<Config, Box<dyn std::error::Error>> {
-    let path = match path {
-        Some(p) => p.to_string(),
-        None => get_default_config_path(),
+/// Read a config file and the files it includes, merged into one document,
+/// with the file each value comes from.
+/// Included files are merged in order, then the including file is merged over them.
+fn load_document(
+    path: &str,
+    source: &str,
+    stack: &mut Vec<String>,
+    files: &mut Vec<String>,
+) -> Result<(Value, Origin), ConfigError> {
+    let (mut document, mut document_origin) = parse_document(path, source)?;
+    let mut include_origin = document_origin.take(INCLUDE_KEY);
+    let patterns = match document.as_object_mut().and_then(|d| d.remove(INCLUDE_KEY)) {
+        None => Vec::new(),
+        Some(Value::String(pattern)) => vec![(pattern, include_origin)],
+        Some(patterns) => {
+            let patterns: Vec<String> = serde_json::from_value(patterns).map_err(|_| {
+                let message = format!("{} has to be a list of paths", INCLUDE_KEY);
+                include_origin.error(message)
+            })?;
+            patterns
+                .into_iter()
+                .enumerate()
+                .map(|(index, pattern)| (pattern, include_origin.take(&index.to_string())))
+                .collect()
+        }
     };
 
-    let f = File::open(&path).expect("Failed opening file");
-    serde_yaml::from_reader(f).map_err(|e| e.into())
+    let mut merged = Value::Object(Map::new());
+    let mut origin = Origin::new(path);
+    for (pattern, pattern_origin) in patterns {
+        for included in
+            find_included_files(path, &pattern).map_err(|message| pattern_origin.error(message))?
+        {
+            // the same file may be reached through different paths
+            if stack.contains(&included) || stack.len() > MAX_INCLUDE_DEPTH {
+                let message = format!("{} is included recursively", included);
+                return Err(pattern_origin.error(message));
+            }
+            let included_source = std::fs::read_to_string(&included)
+                .map_err(|e| ConfigError::Io(included.clone(), e))?;
+            stack.push(included.clone());
+            if !files.contains(&included) {
+                files.push(included.clone());
+            }
+            let (included_document, included_origin) =
+                load_document(&included, &included_source, stack, files)?;
+            stack.pop();
+            merge(
+                &mut merged,
+                &mut origin,
+                included_document,
+                included_origin,
+                0,
+            );
+        }
+    }
+    merge(&mut merged, &mut origin, document, document_origin, 0);
+    Ok((merged, origin))
+}
+
+/// Parse a YAML or TOML file, without building the config yet,
+/// with where each of its values is written.
+fn parse_document(path: &str, source: &str) -> Result<(Value, Origin), ConfigError> {
+    let format = ConfigFormat::from_path(path);
+    let parsed = match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(source).map_err(|e| {
+            let location = e.location().map(|l| (l.line(), l.column()));
+            let mut message = e.to_string();
+            if let Some((line, column)) = location {
+                // the location is part of the diagnostic already
+                let suffix = format!(" at line {} column {}", line, column);
+                if let Some(stripped) = message.strip_suffix(&suffix) {
+                    message = stripped.to_string();
+                }
+            }
+            (location, message)
+        }),
+        ConfigFormat::Toml => toml::from_str(source).map_err(|e| {
+            let location = e.span().map(|span| location_of(source, span.start));
+            (location, e.message().to_string())
+        }),
+        ConfigFormat::Ron => Err((None, "RON files cannot be included".into())),
+    };
+    let document = parsed.map_err(|(location, message)| {
+        ConfigError::Parse(Diagnostic {
+            path: path.into(),
+            location,
+            message,
+        })
+    })?;
+    // the parsed values have no positions, the file is read again for them
+    let origin = match format {
+        ConfigFormat::Yaml => yaml_origin(path, source),
+        ConfigFormat::Toml => toml_origin(path, source),
+        ConfigFormat::Ron => None,
+    };
+    Ok((document, origin.unwrap_or_else(|| Origin::new(path))))
+}
+
+/// Where the values of a YAML file are written, read from the events of the parser.
+fn yaml_origin(path: &str, source: &str) -> Option<Origin> {
+    let mut events = yaml_events(source)?.into_iter();
+    let mut origin = Origin::new(path);
+    if let Some((root, _)) = events.next() {
+        add_yaml_children(&mut origin, root, &mut events)?;
+    }
+    Some(origin)
+}
+
+/// The events of a YAML file that shape its values, with their line and column.
+/// Those of the stream and the document are left out.
+fn yaml_events(source: &str) -> Option<Vec<(YamlEvent, (usize, usize))>> {
+    use unsafe_libyaml::*;
+
+    let mut events = Vec::new();
+    // SAFETY: the parser is initialized before use and deleted once, every event is
+    // read as the type it has then deleted, the input outlives the parser
+    unsafe {
+        let mut parser = std::mem::MaybeUninit::<yaml_parser_t>::uninit();
+        let parser = parser.as_mut_ptr();
+        if yaml_parser_initialize(parser).fail {
+            return None;
+        }
+        yaml_parser_set_input_string(parser, source.as_ptr(), source.len() as u64);
+        let mut event = std::mem::MaybeUninit::<yaml_event_t>::uninit();
+        let event = event.as_mut_ptr();
+        let parsed = loop {
+            if yaml_parser_parse(parser, event).fail {
+                break false;
+            }
+            let event_type = (*event).type_;
+            let kind = match event_type {
+                YAML_SCALAR_EVENT => {
+                    let scalar = &(*event).data.scalar;
+                    let value = std::slice::from_raw_parts(scalar.value, scalar.length as usize);
+                    Some(YamlEvent::Scalar(
+                        String::from_utf8_lossy(value).into_owned(),
+                    ))
+                }
+                YAML_ALIAS_EVENT => Some(YamlEvent::Alias),
+                YAML_SEQUENCE_START_EVENT => Some(YamlEvent::SequenceStart),
+                YAML_MAPPING_START_EVENT => Some(YamlEvent::MappingStart),
+                YAML_SEQUENCE_END_EVENT | YAML_MAPPING_END_EVENT => Some(YamlEvent::End),
+                _ => None,
+            };
+            let mark = (*event).start_mark;
+            yaml_event_delete(event);
+            if let Some(kind) = kind {
+                events.push((kind, (mark.line as usize + 1, mark.column as usize + 1)));
+            }
+            if event_type == YAML_STREAM_END_EVENT {
+                break true;
+            }
+        };
+        yaml_parser_delete(parser);
+        parsed.then_some(events)
+    }
 }
 
-pub fn get_default_config_path() -> String {
-    let xdg_config_home = std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
+enum YamlEvent {
+    Scalar(String),
+    Alias,
+    SequenceStart,
+    MappingStart,
+    /// Of a sequence or a mapping
+    End,
+}
+
+/// Record where the values inside the one started by `event` are written.
+/// Entries of a mapping are placed at their key.
+fn add_yaml_children<I>(origin: &mut Origin, event: YamlEvent, events: &mut I) -> Option<()>
+where
+    I: Iterator<Item = (YamlEvent, (usize, usize))>,
+{
+    let is_mapping = match event {
+        YamlEvent::MappingStart => true,
+        YamlEvent::SequenceStart => false,
+        _ => return Some(()),
+    };
+    for index in 0.. {
+        let (event, location) = events.next()?;
+        let (key, value) = match event {
+            YamlEvent::End => break,
+            YamlEvent::Scalar(key) if is_mapping => (key, events.next()?.0),
+            // keys of a config are strings
+            _ if is_mapping => return None,
+            item => (index.to_string(), item),
+        };
+        let mut child = Origin::new(&origin.file);
+        child.location = Some(location);
+        add_yaml_children(&mut child, value, events)?;
+        origin.children.insert(key, child);
+    }
+    Some(())
+}
+
+/// Where the values of a TOML file are written, read with the spans of their keys.
+fn toml_origin(path: &str, source: &str) -> Option<Origin> {
+    let root: TomlNode = toml::from_str(source).ok()?;
+    let mut origin = Origin::new(path);
+    add_toml_children(&mut origin, root, source);
+    Some(origin)
+}
+
+/// A TOML value reduced to its shape, with the spans of its keys and items.
+enum TomlNode {
+    Table(Vec<(toml::Spanned<String>, TomlNode)>),
+    Array(Vec<toml::Spanned<TomlNode>>),
+    Other,
+}
+
+impl<'de> Deserialize<'de> for TomlNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(TomlNodeVisitor)
+    }
+}
+
+struct TomlNodeVisitor;
+
+impl<'de> Visitor<'de> for TomlNodeVisitor {
+    type Value = TomlNode;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a TOML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<TomlNode, E> {
+        Ok(TomlNode::Other)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Other)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Other)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<TomlNode, E> {
+        Ok(TomlNode::Other)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<TomlNode, E> {
+        Ok(TomlNode::Other)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<TomlNode, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(TomlNode::Array(items))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<TomlNode, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut entries = Vec::new();
+        while let Some(key) = map.next_key()? {
+            entries.push((key, map.next_value()?));
+        }
+        Ok(TomlNode::Table(entries))
+    }
+}
+
+fn add_toml_children(origin: &mut Origin, node: TomlNode, source: &str) {
+    let children: Vec<_> = match node {
+        TomlNode::Table(entries) => entries
+            .into_iter()
+            .map(|(key, value)| (key.span().start, key.into_inner(), value))
+            .collect(),
+        TomlNode::Array(items) => items
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| (item.span().start, index.to_string(), item.into_inner()))
+            .collect(),
+        TomlNode::Other => Vec::new(),
+    };
+    for (start, key, node) in children {
+        let mut child = Origin::new(&origin.file);
+        child.location = Some(location_of(source, start));
+        add_toml_children(&mut child, node, source);
+        origin.children.insert(key, child);
+    }
+}
+
+/// Files matched by an include pattern, sorted. Relative patterns start from
+/// the directory of the including file. A pattern without wildcards has to match.
+fn find_included_files(including: &str, pattern: &str) -> Result<Vec<String>, String> {
+    let mut pattern = expand_string(pattern, &HashMap::new());
+    if Path::new(&pattern).is_relative() {
+        if let Some(dir) = Path::new(including).parent() {
+            pattern = dir.join(&pattern).to_string_lossy().into_owned();
+        }
+    }
+    let mut files: Vec<String> = glob::glob(&pattern)
+        .map_err(|e| format!("invalid include pattern {}: {}", pattern, e))?
+        .filter_map(|entry| entry.ok())
+        .filter(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    if files.is_empty() && !pattern.contains(['*', '?', '[']) {
+        return Err(format!("included file {} does not exist", pattern));
+    }
+    Ok(files)
+}
+
+/// Where the values of a merged document come from, shaped like the document.
+/// A value without an entry in `children` is placed at its parent.
+#[derive(Debug, Default)]
+struct Origin {
+    file: String,
+    // Line and column of the value, or of its key in a mapping
+    location: Option<(usize, usize)>,
+    // By key, or by index for lists
+    children: HashMap<String, Origin>,
+}
+
+impl Origin {
+    fn new(file: &str) -> Self {
+        Self {
+            file: file.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Remove the origin of a child value and return it.
+    fn take(&mut self, key: &str) -> Origin {
+        self.children.remove(key).unwrap_or_else(|| Origin {
+            file: self.file.clone(),
+            location: self.location,
+            children: HashMap::new(),
+        })
+    }
+
+    /// The origin of the value at `path`, if it has one.
+    fn get<S: AsRef<str>>(&self, path: &[S]) -> Option<&Origin> {
+        path.iter()
+            .try_fold(self, |origin, key| origin.children.get(key.as_ref()))
+    }
+
+    /// The origin of the value at `path`, or of the closest value above it.
+    fn at<S: AsRef<str>>(&self, path: &[S]) -> &Origin {
+        let mut origin = self;
+        for key in path {
+            match origin.children.get(key.as_ref()) {
+                Some(child) => origin = child,
+                None => break,
+            }
+        }
+        origin
+    }
+
+    /// `at` for the path of a deserialization error.
+    fn at_path(&self, path: &serde_path_to_error::Path) -> &Origin {
+        let keys: Vec<String> = path
+            .iter()
+            .map_while(|segment| match segment {
+                serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+                serde_path_to_error::Segment::Seq { index } => Some(index.to_string()),
+                _ => None,
+            })
+            .collect();
+        self.at(&keys)
+    }
+
+    fn error(&self, message: String) -> ConfigError {
+        ConfigError::Parse(Diagnostic {
+            path: self.file.clone(),
+            location: self.location,
+            message,
+        })
+    }
+}
+
+/// Merge `overlay` into `base`, and the origins of its values into `base_origin`.
+/// Mappings are merged key by key and the lists at the top level of the config,
+/// such as `rules`, are appended to. Any other value of `overlay` replaces the one of `base`.
+fn merge(
+    base: &mut Value,
+    base_origin: &mut Origin,
+    overlay: Value,
+    mut overlay_origin: Origin,
+    depth: usize,
+) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let value_origin = overlay_origin.take(&key);
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        let file = base_origin.file.clone();
+                        let existing_origin = base_origin
+                            .children
+                            .entry(key)
+                            .or_insert_with(|| Origin::new(&file));
+                        merge(existing, existing_origin, value, value_origin, depth + 1);
+                    }
+                    None => {
+                        base_origin.children.insert(key.clone(), value_origin);
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(overlay)) if depth == 1 => {
+            for (index, value) in overlay.into_iter().enumerate() {
+                let value_origin = overlay_origin.take(&index.to_string());
+                base_origin
+                    .children
+                    .insert(base.len().to_string(), value_origin);
+                base.push(value);
+            }
+        }
+        (base, overlay) => {
+            *base = overlay;
+            *base_origin = overlay_origin;
+        }
+    }
+}
+
+/// Expand every string of a document, keys included, renaming their origins too.
+fn expand_value(value: &mut Value, origin: &mut Origin, vars: &HashMap<String, String>) {
+    match value {
+        Value::String(text) => *text = expand_string(text, vars),
+        Value::Array(values) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                let key = index.to_string();
+                let mut value_origin = origin.take(&key);
+                expand_value(value, &mut value_origin, vars);
+                origin.children.insert(key, value_origin);
+            }
+        }
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    let mut value_origin = origin.take(&key);
+                    expand_value(&mut value, &mut value_origin, vars);
+                    let key = expand_string(&key, vars);
+                    origin.children.insert(key.clone(), value_origin);
+                    (key, value)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// Substitute `$name` with the config variable `name`, `${NAME}` with the environment
+/// variable `NAME` and a `~` starting a word with the home directory.
+/// A `$` not followed by a known variable name is kept, as in `^URxvt$`, and so is an
+/// unset environment variable. `$$` is a `$` left as is, for the shell of a command
+/// to expand `$${NAME}` itself.
+fn expand_string(text: &str, vars: &HashMap<String, String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut word_start = true;
+    while let Some(c) = rest.chars().next() {
+        if c == '~' && word_start && (rest.len() == 1 || rest[1..].starts_with('/')) {
+            expanded.push_str(&std::env::var("HOME").unwrap_or_default());
+            rest = &rest[1..];
+            word_start = false;
+            continue;
+        }
+        if c == '$' {
+            if rest[1..].starts_with('$') {
+                expanded.push('$');
+                rest = &rest[2..];
+                word_start = false;
+                continue;
+            }
+            if let Some((value, after)) = rest
+                .strip_prefix("${")
+                .and_then(|inner| inner.split_once('}'))
+                .and_then(|(name, after)| Some((std::env::var(name).ok()?, after)))
+            {
+                expanded.push_str(&value);
+                rest = after;
+                word_start = false;
+                continue;
+            }
+            let name_len = rest[1..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - 1);
+            if let Some(value) = vars.get(&rest[1..1 + name_len]) {
+                expanded.push_str(value);
+                rest = &rest[1 + name_len..];
+                word_start = false;
+                continue;
+            }
+        }
+        expanded.push(c);
+        word_start = c.is_whitespace();
+        rest = &rest[c.len_utf8()..];
+    }
+    expanded
+}
+
+/// Options values may be written without `Some(...)`, as in the other formats.
+fn ron_options() -> ron::Options {
+    ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+}
+
+/// The config file to read: the given path, which has to exist,
+/// or else the first existing file of the search paths.
+pub fn find_config_path(path: Option<&str>) -> Option<String> {
+    if let Some(path) = path {
+        return Some(path.into());
+    }
+    get_config_search_paths()
+        .into_iter()
+        .find(|p| std::path::Path::new(p).is_file())
+}
+
+/// Where a config file is looked for, in order:
+/// `$XDG_CONFIG_HOME/lazywm/config.yaml`, `~/.lazywm.yaml`,
+/// then `lazywm/config.yaml` in every directory of `$XDG_CONFIG_DIRS`.
+/// Each location is tried with the `.yaml`, `.yml`, `.toml` and `.ron` extensions.
+pub fn get_config_search_paths() -> Vec<String> {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let xdg_config_home = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| format!("{}/.config", home));
+    let xdg_config_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".into());
+
+    let mut locations = vec![
+        format!("{}/{}/{}", xdg_config_home, PACKAGE_NAME, CONFIG_FILE_STEM),
+        format!("{}/.{}", home, PACKAGE_NAME),
+    ];
+    locations.extend(
+        xdg_config_dirs
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(|d| format!("{}/{}/{}", d, PACKAGE_NAME, CONFIG_FILE_STEM)),
+    );
+    locations
+        .iter()
+        .flat_map(|location| {
+            ConfigFormat::EXTENSIONS
+                .iter()
+                .map(move |extension| format!("{}.{}", location, extension))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn context(hostname: &str, outputs: &[&str]) -> ConfigContext {
+        ConfigContext {
+            hostname: hostname.into(),
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+        }
+    }
+
+    fn condition(value: Value) -> Condition {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn condition_without_requirements_always_holds() {
+        assert!(condition(json!({})).holds(&context("desk", &[])));
+    }
+
+    #[test]
+    fn condition_matches_the_hostname_as_a_pattern() {
+        let laptop = condition(json!({ "hostname": "^lap" }));
+        assert!(laptop.holds(&context("laptop", &[])));
+        assert!(!laptop.holds(&context("desk", &[])));
+    }
+
+    #[test]
+    fn condition_counts_and_names_the_outputs() {
+        let docked = condition(json!({ "outputs": 2, "output": "^HDMI" }));
+        assert!(docked.holds(&context("laptop", &["eDP-1", "HDMI-1"])));
+        assert!(!docked.holds(&context("laptop", &["eDP-1", "DP-1"])));
+        assert!(!docked.holds(&context("laptop", &["HDMI-1"])));
+        assert!(docked.depends_on_outputs());
+        assert!(!condition(json!({ "hostname": "laptop" })).depends_on_outputs());
+    }
+
+    fn key(modifier_mask: KeyButMask, keysym: Keysym) -> KeyCompound {
+        KeyCompound {
+            modifier_mask: modifier_mask.into(),
+            keysym,
+        }
+    }
+
+    fn command(command: &str) -> KeyCommand {
+        KeyCommand {
+            command: command.into(),
+            release: false,
+        }
+    }
+
+    fn parse_error(path: &str, source: &str) -> String {
+        match parse_config(path.into(), source.into(), &ConfigContext::default()) {
+            Ok(_) => panic!("{} parsed", path),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn key_compounds_are_modifiers_then_a_keysym() {
+        assert_eq!(
+            parse_key_compound(" super + Shift+h "),
+            Ok(key(
+                KeyButMask::MOD4 | KeyButMask::SHIFT,
+                xkbcommon::xkb::KEY_h
+            ))
+        );
+        assert_eq!(
+            parse_key_compound("Return"),
+            Ok(key(KeyButMask::default(), xkbcommon::xkb::KEY_Return))
+        );
+        assert_eq!(
+            parse_key_compound("hyper+h"),
+            Err("unknown modifier hyper in hyper+h".into())
+        );
+        assert_eq!(
+            parse_key_compound("shift+nokey"),
+            Err("unknown key nokey in shift+nokey".into())
+        );
+    }
+
+    #[test]
+    fn key_sequences_share_their_prefix() {
+        let w = key(KeyButMask::default(), xkbcommon::xkb::KEY_w);
+        let h = key(KeyButMask::default(), xkbcommon::xkb::KEY_h);
+        let l = key(KeyButMask::default(), xkbcommon::xkb::KEY_l);
+        let mut key_map = HashMap::new();
+        insert_key_sequence(&mut key_map, &[w, h], command("focus left")).unwrap();
+        insert_key_sequence(&mut key_map, &[w, l], command("focus right")).unwrap();
+        insert_key_sequence(&mut key_map, &[h], command("close")).unwrap();
+
+        let Some(KeyBinding::Prefix(next)) = key_map.get(&w) else {
+            panic!("w does not start a sequence");
+        };
+        assert!(matches!(next.get(&h), Some(KeyBinding::Command(c)) if c.command == "focus left"));
+        assert!(matches!(next.get(&l), Some(KeyBinding::Command(c)) if c.command == "focus right"));
+        assert!(matches!(key_map.get(&h), Some(KeyBinding::Command(c)) if c.command == "close"));
+    }
+
+    #[test]
+    fn key_cannot_both_run_a_command_and_start_a_sequence() {
+        let w = key(KeyButMask::default(), xkbcommon::xkb::KEY_w);
+        let h = key(KeyButMask::default(), xkbcommon::xkb::KEY_h);
+        let mut key_map = HashMap::new();
+        insert_key_sequence(&mut key_map, &[w, h], command("focus left")).unwrap();
+        assert_eq!(
+            insert_key_sequence(&mut key_map, &[w], command("close")),
+            Err(w)
+        );
+        assert_eq!(
+            insert_key_sequence(&mut key_map, &[w, h], command("close")),
+            Err(h)
+        );
+
+        let mut key_map = HashMap::new();
+        insert_key_sequence(&mut key_map, &[w], command("close")).unwrap();
+        assert_eq!(
+            insert_key_sequence(&mut key_map, &[w, h], command("focus left")),
+            Err(w)
+        );
+    }
+
+    #[test]
+    fn key_map_errors_are_placed_at_their_key() {
+        let source = "\
+mod_key: Mod4
+modes:
+  default:
+    key_maps:
+      h: focus left
+      hyper+l: focus right
+";
+        assert_eq!(
+            parse_error("config.yaml", source),
+            "config.yaml:6:7: modes.default.key_maps.hyper+l: unknown modifier hyper in hyper+l"
+        );
+
+        let source = "\
+mod_key = \"Mod4\"
+[modes.default.key_maps]
+w = \"close\"
+\"w, h\" = \"focus left\"
+";
+        assert_eq!(
+            parse_error("config.toml", source),
+            "config.toml:4:1: modes.default.key_maps.w, h: w, h conflicts with w"
+        );
+    }
+
+    #[test]
+    fn check_places_problems_at_the_binding() {
+        let source = "\
+mod_key: Mod4
+modes:
+  default:
+    key_maps:
+      \"w,h\": mode resize
+      q: close
+mouse_bindings:
+  button1 on root: launch
+rules:
+  - match: { class: x }
+    actions: [close, floating]
+";
+        let config = parse_config(
+            "config.yaml".into(),
+            source.into(),
+            &ConfigContext::default(),
+        )
+        .unwrap();
+        let problems: Vec<String> = config
+            .check(&["close".into(), "mode".into()])
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                "config.yaml:5:7: undefined mode resize in mode default",
+                "config.yaml:8:3: unknown command launch in mouse bindings",
+                "config.yaml:11:22: unknown command floating in rules",
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_expand_variables_and_the_home_directory() {
         let home = std::env::var("HOME").unwrap();
-        format!("{}/.config", home)
-    });
+        let vars = HashMap::from([("term".to_string(), "urxvt -e".to_string())]);
+        assert_eq!(
+            expand_string("exec $term htop", &vars),
+            "exec urxvt -e htop"
+        );
+        assert_eq!(expand_string("$term_x", &vars), "$term_x");
+        assert_eq!(expand_string("^URxvt$", &vars), "^URxvt$");
+        assert_eq!(expand_string("${HOME}/bin", &vars), format!("{}/bin", home));
+        assert_eq!(
+            expand_string("~/bin a~/b ~", &vars),
+            format!("{0}/bin a~/b {0}", home)
+        );
+    }
+
+    #[test]
+    fn strings_keep_what_is_left_to_the_shell() {
+        let vars = HashMap::from([("term".to_string(), "urxvt".to_string())]);
+        assert_eq!(
+            expand_string("exec sh -c 'echo $${HOME} $$term $$$$'", &vars),
+            "exec sh -c 'echo ${HOME} $term $$'"
+        );
+        assert_eq!(
+            expand_string("exec echo ${LAZYWM_UNSET_VARIABLE}", &vars),
+            "exec echo ${LAZYWM_UNSET_VARIABLE}"
+        );
+    }
 
-    format!("{}/{}/{}", xdg_config_home, PACKAGE_NAME, CONFIG_FILE)
+    #[test]
+    fn merged_values_keep_the_file_they_are_written_in() {
+        let included = "\
+modes:
+  default:
+    key_maps:
+      q: close
+      h: focus left
+rules:
+  - match: { class: a }
+";
+        let config = "\
+modes:
+  default:
+    key_maps:
+      h: focus right
+rules:
+  - match: { class: b }
+";
+        let (mut merged, mut origin) = parse_document("included.yaml", included).unwrap();
+        let (overlay, overlay_origin) = parse_document("config.yaml", config).unwrap();
+        merge(&mut merged, &mut origin, overlay, overlay_origin, 0);
+
+        assert_eq!(merged["modes"]["default"]["key_maps"]["h"], "focus right");
+        let at = |path: &[&str]| {
+            let origin = origin.at(path);
+            (origin.file.as_str(), origin.location)
+        };
+        assert_eq!(
+            at(&["modes", "default", "key_maps", "q"]),
+            ("included.yaml", Some((4, 7)))
+        );
+        assert_eq!(
+            at(&["modes", "default", "key_maps", "h"]),
+            ("config.yaml", Some((4, 7)))
+        );
+        // a value missing from the origins is placed at the closest one above it
+        assert_eq!(
+            at(&["modes", "default", "key_maps", "x"]),
+            ("included.yaml", Some((3, 5)))
+        );
+        // lists at the top level are appended to
+        assert_eq!(
+            at(&["rules", "0", "match"]),
+            ("included.yaml", Some((7, 5)))
+        );
+        assert_eq!(at(&["rules", "1", "match"]), ("config.yaml", Some((6, 5))));
+    }
+
+    #[test]
+    fn problems_of_included_files_are_placed_in_them() {
+        let dir =
+            std::env::temp_dir().join(format!("{}-include-{}", PACKAGE_NAME, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.yaml").to_string_lossy().into_owned();
+        let included = dir.join("keys.yaml").to_string_lossy().into_owned();
+        std::fs::write(&config, "include: keys.yaml\nmod_key: Mod4\n").unwrap();
+
+        std::fs::write(
+            &included,
+            "modes:\n  default:\n    key_maps:\n      q: quit\n      h: launch\n",
+        )
+        .unwrap();
+        let problems: Vec<String> = load_config(Some(&config), &ConfigContext::default())
+            .unwrap()
+            .check(&["quit".into()])
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            [format!(
+                "{}:5:7: unknown command launch in mode default",
+                included
+            )]
+        );
+
+        std::fs::write(
+            &included,
+            "modes:\n  default:\n    key_maps:\n      q: quit\n      hyper+h: quit\n",
+        )
+        .unwrap();
+        let error = load_config(Some(&config), &ConfigContext::default()).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{}:5:7: modes.default.key_maps.hyper+h: unknown modifier hyper in hyper+h",
+                included
+            )
+        );
+    }
 }
@@ -0,0 +1,198 @@
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::Serialize;
+
+const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
+pub const SOCKET_ENV: &str = "LAZYWM_SOCKET";
+// Longest command line a client may send, it is dropped once it exceeds it
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Events broadcasted to every connected IPC client, one JSON object per line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum IpcEvent {
+    Mode {
+        name: String,
+    },
+    /// Keys of the pending key sequence, empty once it completed or was aborted.
+    Chord {
+        keys: Vec<String>,
+    },
+    /// Workspaces holding a window that needs attention, numbered from 1.
+    Urgent {
+        workspaces: Vec<usize>,
+    },
+    /// Outcome of a config reload, with the parse error when the config was kept.
+    Reload {
+        success: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A command could not be started, or the shell could not run it.
+    SpawnFailed {
+        command: String,
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct IpcReply<'a> {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// A command line written by a client, to be run by the WM.
+pub struct IpcRequest {
+    pub client: RawFd,
+    pub command: String,
+}
+
+struct IpcClient {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+/// Line based IPC over a unix socket.
+/// Every line a client writes is a command, every line it reads is either
+/// the reply to one of its commands or a broadcasted event.
+pub struct Ipc {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: RefCell<Vec<IpcClient>>,
+}
+
+impl Ipc {
+    pub fn bind() -> io::Result<Self> {
+        let path = get_socket_path();
+        // a stale socket from a previous session would make bind fail
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        std::env::set_var(SOCKET_ENV, &path);
+        info!("IPC listening on {}", path.display());
+
+        Ok(Self {
+            listener,
+            path,
+            clients: RefCell::new(Vec::new()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// File descriptors the event loop has to poll for IPC activity.
+    pub fn fds(&self) -> Vec<RawFd> {
+        let mut fds = vec![self.listener.as_raw_fd()];
+        fds.extend(self.clients.borrow().iter().map(|c| c.stream.as_raw_fd()));
+        fds
+    }
+
+    /// Accept pending connections and collect complete command lines.
+    pub fn dispatch(&self) -> Vec<IpcRequest> {
+        let mut clients = self.clients.borrow_mut();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("Cannot set IPC client non-blocking: {}", e);
+                        continue;
+                    }
+                    clients.push(IpcClient {
+                        stream,
+                        buffer: Vec::new(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Cannot accept IPC client: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut requests = Vec::new();
+        clients.retain_mut(|client| {
+            let mut chunk = [0u8; 1024];
+            loop {
+                match client.stream.read(&mut chunk) {
+                    // the client hung up
+                    Ok(0) => return false,
+                    Ok(n) => client.buffer.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => return false,
+                }
+                while let Some(end) = client.buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = client.buffer.drain(..=end).collect();
+                    let command = String::from_utf8_lossy(&line).trim().to_string();
+                    if !command.is_empty() {
+                        requests.push(IpcRequest {
+                            client: client.stream.as_raw_fd(),
+                            command,
+                        });
+                    }
+                }
+                if client.buffer.len() > MAX_LINE_LENGTH {
+                    warn!(
+                        "Dropping IPC client sending a line over {} bytes",
+                        MAX_LINE_LENGTH
+                    );
+                    return false;
+                }
+            }
+            true
+        });
+
+        requests
+    }
+
+    pub fn reply(&self, client: RawFd, result: Result<(), String>) {
+        let reply = IpcReply {
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.as_str()),
+        };
+        let mut clients = self.clients.borrow_mut();
+        if let Some(c) = clients.iter_mut().find(|c| c.stream.as_raw_fd() == client) {
+            let _ = Self::write_line(&mut c.stream, &reply);
+        }
+    }
+
+    pub fn broadcast(&self, event: &IpcEvent) {
+        self.clients
+            .borrow_mut()
+            .retain_mut(|c| Self::write_line(&mut c.stream, event).is_ok());
+    }
+
+    fn write_line<T: Serialize>(stream: &mut UnixStream, value: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        stream.write_all(&line)
+    }
+}
+
+impl Drop for Ipc {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn get_socket_path() -> PathBuf {
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".into());
+    PathBuf::from(format!(
+        "{}/{}.{}.sock",
+        runtime_dir,
+        PACKAGE_NAME,
+        std::process::id()
+    ))
+}
@@ -0,0 +1,178 @@
+use xkbcommon::xkb::{
+    self,
+    x11::{
+        get_core_keyboard_device_id, keymap_new_from_device, setup_xkb_extension,
+        state_new_from_device, SetupXkbExtensionFlags, MIN_MAJOR_XKB_VERSION,
+        MIN_MINOR_XKB_VERSION,
+    },
+    Keycode, Keysym,
+};
+
+use crate::x::{Error, Result};
+
+const SHIFT_MASK: u16 = 1 << 0;
+
+/// The XKB keymap and state of the core keyboard.
+///
+/// The keymap is fetched through a dedicated xcb connection, since xkbcommon-x11
+/// only speaks xcb, and is refreshed whenever the server reports a new keyboard
+/// or mapping. The state is kept in sync from `XkbStateNotify` events.
+pub struct Keyboard {
+    xcb_conn: xcb::Connection,
+    context: xkb::Context,
+    device_id: i32,
+    keymap: xkb::Keymap,
+    state: xkb::State,
+    // Real modifier of ISO_Level3_Shift (AltGr), which reaches the third level
+    level3_mask: Option<u16>,
+}
+
+impl Keyboard {
+    pub fn new() -> Result<Self> {
+        let (xcb_conn, _) =
+            xcb::Connection::connect_with_extensions(None, &[xcb::Extension::Xkb], &[])
+                .map_err(|e| Error::Unknown(format!("cannot open xcb connection: {}", e)))?;
+
+        let (mut major, mut minor, mut base_event, mut base_error) = (0, 0, 0, 0);
+        if !setup_xkb_extension(
+            &xcb_conn,
+            MIN_MAJOR_XKB_VERSION,
+            MIN_MINOR_XKB_VERSION,
+            SetupXkbExtensionFlags::NoFlags,
+            &mut major,
+            &mut minor,
+            &mut base_event,
+            &mut base_error,
+        ) {
+            return Err(Error::Unknown("XKB extension is not supported".into()));
+        }
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let device_id = get_core_keyboard_device_id(&xcb_conn);
+        if device_id == -1 {
+            return Err(Error::Unknown("cannot find the core keyboard".into()));
+        }
+        let keymap =
+            keymap_new_from_device(&context, &xcb_conn, device_id, xkb::KEYMAP_COMPILE_NO_FLAGS);
+        let state = state_new_from_device(&keymap, &xcb_conn, device_id);
+        let level3_mask = level3_modifier(&xcb_conn, &keymap);
+
+        Ok(Self {
+            xcb_conn,
+            context,
+            device_id,
+            keymap,
+            state,
+            level3_mask,
+        })
+    }
+
+    /// Fetch the keymap again, after the layout or the keyboard itself changed.
+    pub fn reload(&mut self) {
+        // the core keyboard may be a different device after XkbNewKeyboardNotify
+        self.device_id = get_core_keyboard_device_id(&self.xcb_conn);
+        self.keymap = keymap_new_from_device(
+            &self.context,
+            &self.xcb_conn,
+            self.device_id,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        );
+        self.state = state_new_from_device(&self.keymap, &self.xcb_conn, self.device_id);
+        self.level3_mask = level3_modifier(&self.xcb_conn, &self.keymap);
+    }
+
+    pub fn update_state(&mut self, event: &x11rb::protocol::xkb::StateNotifyEvent) {
+        self.state.update_mask(
+            u16::from(event.base_mods).into(),
+            u16::from(event.latched_mods).into(),
+            u16::from(event.locked_mods).into(),
+            event.base_group as xkb::LayoutIndex,
+            event.latched_group as xkb::LayoutIndex,
+            u8::from(event.locked_group).into(),
+        );
+    }
+
+    /// Resolve a key press into the keysym it produces with the current group and
+    /// shift level, and the modifiers left once the ones consumed to reach that level
+    /// are removed. For example Shift+equal on a US layout resolves to `plus` without Shift.
+    pub fn resolve(&self, keycode: Keycode, state: u32) -> (Keysym, u32) {
+        let keysym = self.state.key_get_one_sym(keycode);
+        let state = self.state.mod_mask_remove_consumed(keycode, state);
+        (keysym, state)
+    }
+
+    /// The keysym on the first level of the key in the current group,
+    /// so that `shift+h` matches even though Shift+h produces `H`.
+    pub fn base_keysym(&self, keycode: Keycode) -> Keysym {
+        let layout = self.state.key_get_layout(keycode);
+        self.keymap
+            .key_get_syms_by_level(keycode, layout, 0)
+            .first()
+            .copied()
+            .unwrap_or(xkb::KEY_NoSymbol)
+    }
+
+    /// Every key producing `keysym` in any group, with the modifiers
+    /// needed on top of the binding to reach the level of the keysym.
+    /// Levels no modifier of the mapping reaches are left out.
+    pub fn keycodes_for(&self, keysym: Keysym) -> Vec<(Keycode, u16)> {
+        let mut keycodes = Vec::new();
+        for (keycode, level) in keys_producing(&self.keymap, keysym) {
+            // xkbcommon 0.5 cannot tell the modifiers of a level, so the usual
+            // Shift / AltGr arrangement of the first four levels is assumed
+            let modifiers = match level {
+                0 => Some(0),
+                1 => Some(SHIFT_MASK),
+                2 => self.level3_mask,
+                3 => self.level3_mask.map(|mask| mask | SHIFT_MASK),
+                _ => None,
+            };
+            if let Some(modifiers) = modifiers {
+                if !keycodes.contains(&(keycode, modifiers)) {
+                    keycodes.push((keycode, modifiers));
+                }
+            }
+        }
+        keycodes
+    }
+}
+
+/// Keys producing `keysym`, with the level producing it, in any group.
+fn keys_producing(keymap: &xkb::Keymap, keysym: Keysym) -> Vec<(Keycode, xkb::LevelIndex)> {
+    let mut keys = Vec::new();
+    for keycode in keymap.min_keycode()..=keymap.max_keycode() {
+        for layout in 0..keymap.num_layouts_for_key(keycode) {
+            for level in 0..keymap.num_levels_for_key(keycode, layout) {
+                if keymap
+                    .key_get_syms_by_level(keycode, layout, level)
+                    .contains(&keysym)
+                {
+                    keys.push((keycode, level));
+                }
+            }
+        }
+    }
+    keys
+}
+
+/// The real modifier the ISO_Level3_Shift key is mapped to in the modifier
+/// mapping of the server, usually Mod5. None when no such key is mapped.
+fn level3_modifier(conn: &xcb::Connection, keymap: &xkb::Keymap) -> Option<u16> {
+    let cookie = conn.send_request(&xcb::x::GetModifierMapping {});
+    let mapping = conn.wait_for_reply(cookie).ok()?;
+    let keycodes_per_modifier = mapping.keycodes_per_modifier() as usize;
+    let level3_keys = keys_producing(keymap, xkb::KEY_ISO_Level3_Shift);
+    mapping
+        .keycodes()
+        .iter()
+        .position(|&k| k != 0 && level3_keys.iter().any(|&(c, _)| c == k as Keycode))
+        .map(|i| 1 << (i / keycodes_per_modifier))
+}
+
+/// Whether the keysym belongs to a modifier key, such as `Shift_L` or `ISO_Level3_Shift`.
+pub fn is_modifier(keysym: Keysym) -> bool {
+    (xkb::KEY_Shift_L..=xkb::KEY_Hyper_R).contains(&keysym)
+        || (xkb::KEY_ISO_Lock..=xkb::KEY_ISO_Level5_Lock).contains(&keysym)
+        || keysym == xkb::KEY_Mode_switch
+        || keysym == xkb::KEY_Num_Lock
+}
@@ -1,4 +1,8 @@
 pub mod config;
+pub mod ipc;
+pub mod keyboard;
+pub mod signals;
+pub mod watcher;
 pub mod wm;
 pub mod wm_state;
 pub mod x;
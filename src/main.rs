@@ -1,16 +1,62 @@
+use std::process::exit;
+
 use clap::Parser;
-use lazywm::{config, wm::WM};
+use lazywm::{
+    config::{self, ConfigContext, ConfigFormat},
+    wm::WM,
+};
 use log::{info, LevelFilter};
 
 mod cli;
 
 fn main() {
     let args = cli::Args::parse();
-    let config =
-        config::load_config(args.config.as_ref().map(|c| c.as_str())).expect("config cannot load");
-    let wm = WM::new(config).unwrap();
+    if args.dump_default_config {
+        print!("{}", config::DEFAULT_CONFIG);
+        exit(0);
+    }
+    // the outputs are only known once connected, the WM loads the config
+    // again if it depends on them
+    let context = ConfigContext::new(Vec::new());
+    let config = match config::load_config(args.config.as_deref(), &context) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    if args.check_config {
+        let diagnostics = config.check(&WM::command_names(&config));
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        exit(if diagnostics.is_empty() { 0 } else { 1 });
+    }
+    if let Some(format) = args.dump_config {
+        let dumped = format
+            .parse::<ConfigFormat>()
+            .map_err(|_| format!("unknown config format {}", format))
+            .and_then(|format| config.dump(format));
+        match dumped {
+            Ok(dumped) => print!("{}", dumped),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+    // the config loaded again for the connected outputs may not parse
+    let wm = match WM::new(config, args.config) {
+        Ok(wm) => wm,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
     systemd_journal_logger::init().unwrap();
     log::set_max_level(LevelFilter::Info);
     wm.init();
+    wm.autostart();
     wm.run();
 }
@@ -0,0 +1,95 @@
+use std::{
+    io,
+    os::unix::{io::AsRawFd, io::RawFd, process::CommandExt},
+    process::Command,
+};
+
+use log::warn;
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{SigSet, SigmaskHow, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::setsid,
+};
+
+/// Signals handled by the WM, delivered through a file descriptor polled
+/// by the event loop instead of interrupting it.
+const HANDLED_SIGNALS: [Signal; 2] = [Signal::SIGHUP, Signal::SIGCHLD];
+
+pub struct Signals {
+    fd: SignalFd,
+}
+
+impl Signals {
+    /// Block the handled signals and receive them through a signalfd.
+    pub fn new() -> io::Result<Self> {
+        let mask = handled_mask();
+        mask.thread_block()?;
+        let fd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)?;
+        Ok(Self { fd })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// Signals received since the last call.
+    pub fn dispatch(&mut self) -> Vec<Signal> {
+        let mut signals = Vec::new();
+        loop {
+            match self.fd.read_signal() {
+                Ok(Some(info)) => match Signal::try_from(info.ssi_signo as i32) {
+                    Ok(signal) => signals.push(signal),
+                    Err(e) => warn!("Unknown signal {}: {}", info.ssi_signo, e),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Cannot read signals: {}", e);
+                    break;
+                }
+            }
+        }
+        signals
+    }
+}
+
+/// Unblock the handled signals in spawned programs, which would otherwise inherit
+/// the signal mask of the WM, and start them in their own session so that they
+/// outlive it.
+pub fn detach_child(command: &mut Command) -> &mut Command {
+    unsafe {
+        command.pre_exec(|| {
+            nix::sys::signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&handled_mask()), None)?;
+            setsid()?;
+            Ok(())
+        })
+    }
+}
+
+/// Collect the children that exited, so that they do not linger as zombies.
+/// Several exits may be reported by a single SIGCHLD.
+pub fn reap_children() -> Vec<WaitStatus> {
+    let mut statuses = Vec::new();
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+            Ok(status) => statuses.push(status),
+            Err(e) => {
+                warn!("Cannot wait for children: {}", e);
+                break;
+            }
+        }
+    }
+    statuses
+}
+
+fn handled_mask() -> SigSet {
+    let mut mask = SigSet::empty();
+    for signal in HANDLED_SIGNALS {
+        mask.add(signal);
+    }
+    mask
+}
@@ -0,0 +1,76 @@
+use std::{
+    ffi::OsString,
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+/// Notices changes to the config files through inotify.
+///
+/// The directories of the files are watched rather than the files themselves,
+/// since editors commonly save by writing a new file and renaming it over the old one,
+/// which leaves a watch on the replaced inode without further events.
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    // Watched directories with the names of the files of interest in each
+    watches: Vec<(WatchDescriptor, Vec<OsString>)>,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: AsRef<Path>>(files: &[P]) -> io::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut watches: Vec<(WatchDescriptor, Vec<OsString>)> = Vec::new();
+        for file in files {
+            let file = file.as_ref();
+            let (Some(name), Some(dir)) = (file.file_name(), file.parent()) else {
+                continue;
+            };
+            let dir = if dir.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                dir.to_path_buf()
+            };
+            let flags = AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE;
+            // watching a directory twice returns the same descriptor
+            let wd = inotify.add_watch(&dir, flags)?;
+            match watches.iter_mut().find(|(w, _)| *w == wd) {
+                Some((_, names)) => names.push(name.to_os_string()),
+                None => watches.push((wd, vec![name.to_os_string()])),
+            }
+        }
+        Ok(Self { inotify, watches })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+
+    /// Whether one of the files changed since the last call.
+    pub fn dispatch(&self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.inotify.read_events() {
+                Ok(events) => {
+                    changed |= events.iter().any(|event| {
+                        self.watches.iter().any(|(wd, names)| {
+                            *wd == event.wd
+                                && event.name.as_ref().is_some_and(|n| names.contains(n))
+                        })
+                    })
+                }
+                Err(nix::errno::Errno::EAGAIN) => break,
+                Err(e) => {
+                    warn!("Cannot read config file changes: {}", e);
+                    break;
+                }
+            }
+        }
+        changed
+    }
+}
@@ -1,48 +1,166 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet},
+    os::unix::io::AsRawFd,
     process::{exit, Command, Stdio},
     rc::Rc,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use log::info;
+use log::{info, warn};
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::{signal::Signal, wait::WaitStatus},
+};
 use strum::IntoEnumIterator;
 use strum_macros::{AsRefStr, EnumIter, EnumString};
 use x11rb::{
-    connection::Connection,
+    connection::{Connection, SequenceNumber},
     cursor,
+    properties::{WmClass, WmHints},
     protocol::{
+        randr::{self, ConnectionExt as _},
+        xkb::{self, ConnectionExt as _},
         xproto::{
-            ButtonPressEvent, ChangeWindowAttributesAux, Circulate, ConfigureRequestEvent,
+            Allow, AtomEnum, ButtonIndex, ButtonPressEvent, ButtonReleaseEvent,
+            ChangeWindowAttributesAux, ClientMessageEvent, ConfigureRequestEvent,
             ConfigureWindowAux, ConnectionExt, CreateWindowAux, Cursor, EnterNotifyEvent,
-            EventMask, FocusInEvent, FocusOutEvent, Font, GrabMode, InputFocus, KeyPressEvent,
-            MapRequestEvent, MapState, ModMask, Screen, SetMode, StackMode, UnmapNotifyEvent,
-            Window,
+            EventMask, FocusInEvent, FocusOutEvent, Font, Grab, GrabMode, GrabStatus, InputFocus,
+            KeyPressEvent, KeyReleaseEvent, MapRequestEvent, MapState, Mapping, MappingNotifyEvent,
+            ModMask, MotionNotifyEvent, NotifyDetail, NotifyMode, PropMode, PropertyNotifyEvent,
+            Screen, SetMode, StackMode, Timestamp, UnmapNotifyEvent, Window,
         },
         Event,
     },
     resource_manager,
     rust_connection::RustConnection,
-    CURRENT_TIME,
+    wrapper::ConnectionExt as _,
+    CURRENT_TIME, NONE,
 };
+use xkbcommon::xkb::{Keycode, Keysym};
 
 use crate::{
-    config::Config,
-    wm_state::WmState,
+    config::{
+        self, Autostart, ButtonCompound, ButtonContext, Config, ConfigContext,
+        FocusOnWindowActivation, FocusPolicy, KeyBinding, KeyCommand, KeyCompound, KeyMap,
+        WindowCriteria, WindowProperties, DEFAULT_MODE,
+    },
+    ipc::{self, Ipc, IpcEvent},
+    keyboard::{self, Keyboard},
+    signals::{self, Signals},
+    watcher::ConfigWatcher,
+    wm_state::{Direction, LayoutType, WmState},
     x::{Error, Result},
 };
 
 #[derive(
     AsRefStr, EnumIter, EnumString, Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy,
 )]
-pub enum Atom {}
+#[allow(non_camel_case_types)]
+pub enum Atom {
+    _NET_SUPPORTED,
+    _NET_ACTIVE_WINDOW,
+    _NET_WM_STATE,
+    _NET_WM_STATE_DEMANDS_ATTENTION,
+    _NET_WM_NAME,
+    _NET_WM_WINDOW_TYPE,
+    _NET_WM_PID,
+    _NET_STARTUP_ID,
+    WM_PROTOCOLS,
+    WM_TAKE_FOCUS,
+    WM_WINDOW_ROLE,
+    // Touched on the root window to get a timestamp from the server
+    _LAZYWM_TIMESTAMP,
+    // Set on the root window once the autostart programs ran in this X session
+    _LAZYWM_AUTOSTARTED,
+}
+
+// Actions of a `_NET_WM_STATE` client message
+const NET_WM_STATE_REMOVE: u32 = 0;
+const NET_WM_STATE_ADD: u32 = 1;
+const NET_WM_STATE_TOGGLE: u32 = 2;
+// Source of a `_NET_ACTIVE_WINDOW` request coming from a pager or taskbar
+const SOURCE_PAGER: u32 = 2;
+
+const NUM_WORKSPACES: usize = 10;
+const FRAME_BORDER_WIDTH: u32 = 2;
+const FOCUSED_BORDER_PIXEL: u32 = 0x4c7899;
+const UNFOCUSED_BORDER_PIXEL: u32 = 0x333333;
+const URGENT_BORDER_PIXEL: u32 = 0x900000;
+// Editors write a file in several steps, the reload waits for them to settle
+const CONFIG_RELOAD_DELAY: Duration = Duration::from_millis(200);
+// A program started for a workspace that shows no window by then is given up on
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(15);
+const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
+// Smallest size a window can be resized to with the pointer
+const MIN_DRAG_SIZE: u32 = 50;
+
+struct PendingChord {
+    // Keys typed so far, for display
+    keys: Vec<String>,
+    // Bindings reachable from those keys
+    key_map: KeyMap,
+    deadline: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DragKind {
+    Move,
+    Resize,
+}
+
+/// A floating window moved or resized with the pointer until the button is released.
+struct Drag {
+    kind: DragKind,
+    // Pointer position on the root window when the drag started
+    pointer: (i16, i16),
+    // Position and size of the window when the drag started
+    geometry: (u32, u32, u32, u32),
+}
+
+/// A program started for a workspace, waiting for its first window.
+/// The window is recognized by its process id, or that of a descendant,
+/// or by the startup notification id the program was given.
+struct PendingSpawn {
+    pid: u32,
+    startup_id: String,
+    workspace: usize,
+    deadline: Instant,
+}
+
+/// Where the need for attention of a window comes from. The client sets and clears
+/// each one on its own, the window needs attention while either is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Urgency {
+    // The urgency hint of `WM_HINTS`
+    Hint,
+    // `_NET_WM_STATE_DEMANDS_ATTENTION`, also set by the WM for refused activations
+    DemandsAttention,
+}
+
+/// A named scratchpad spawned by a toggle, waiting for its window.
+/// It is given up on when the program exits or the deadline passes.
+struct PendingScratchpad {
+    name: String,
+    pid: u32,
+    deadline: Instant,
+}
+
+/// Windows being cycled through with `focus next` and `focus prev`
+/// while the modifiers of the binding are held.
+struct FocusCycle {
+    // The focus history when the cycle started
+    windows: Vec<Window>,
+    index: usize,
+}
 
 pub struct Client {
     frame_win: Window,
     client_win: Window,
 }
 
-type Handler = Box<dyn Fn(&WM) -> Result<()>>;
+type Handler = Box<dyn Fn(&WM, &[&str]) -> Result<()>>;
 
 pub struct WM {
     atoms: HashMap<Atom, u32>,
@@ -52,18 +170,70 @@ pub struct WM {
     window_frame_map: RefCell<HashMap<Window, Window>>,
 
     running: RefCell<bool>,
-    focusing_client: RefCell<Option<Rc<Client>>>,
-    // Stack of original client window
-    display_stack: RefCell<VecDeque<Window>>,
+    // Focused windows of every workspace, the most recent first
+    focus_history: RefCell<Vec<Window>>,
+    // Workspace shown before the current one, for back_and_forth
+    previous_workspace: RefCell<Option<usize>>,
+    focus_cycle: RefCell<Option<FocusCycle>>,
     normal_cursor: Cursor,
-    config: Config,
-    commands: HashMap<String, Handler>,
+    // Cursors of the root window from the cursor theme, loaded by init
+    root_cursor: RefCell<Cursor>,
+    busy_cursor: RefCell<Cursor>,
+    drag_cursor: RefCell<Cursor>,
+    config: RefCell<Config>,
+    // Path given on the command line, the default one is used otherwise
+    config_path: Option<String>,
+    // Config parsed by the reload command, swapped in once the command returned
+    pending_config: RefCell<Option<Config>>,
+    // Watches the config files when auto_reload is on
+    config_watcher: RefCell<Option<ConfigWatcher>>,
+    // When to reload after the config files changed
+    reload_deadline: RefCell<Option<Instant>>,
+    // Whether the server supports RandR 1.3, needed to list the outputs
+    has_randr: bool,
+    // Names of the connected outputs, conditional config sections depend on them
+    outputs: RefCell<Vec<String>>,
+    commands: RefCell<HashMap<String, Handler>>,
     wm_state: RefCell<WmState>,
-    wm_mode: String,
+    wm_mode: RefCell<String>,
+    // When the current mode falls back to the default one
+    mode_deadline: RefCell<Option<Instant>>,
+    pending_chord: RefCell<Option<PendingChord>>,
+    // Pointer position and time of the press running a mouse binding, drags start there
+    button_press: RefCell<Option<(i16, i16, Timestamp)>>,
+    drag: RefCell<Option<Drag>>,
+    // Commands of release bindings, by the keycode that is still held
+    release_commands: RefCell<HashMap<Keycode, String>>,
+    keyboard: RefCell<Keyboard>,
+    // Geometry last sent to the X server for each frame
+    frame_geometries: RefCell<HashMap<Window, (u32, u32, u32, u32)>>,
+    // EnterNotify events up to this request were caused by the WM itself
+    ignore_enter_until: RefCell<SequenceNumber>,
+    // Modifier masks of NumLock and ScrollLock
+    lock_masks: RefCell<(u16, u16)>,
+    // Server time of the latest event that carried one
+    last_timestamp: RefCell<Timestamp>,
+    // Indices of the window rules already applied to each client
+    applied_rules: RefCell<HashMap<Window, Vec<usize>>>,
+    // Clients with the urgency hint set, and those demanding attention
+    urgency_hints: RefCell<HashSet<Window>>,
+    demanding_attention: RefCell<HashSet<Window>>,
+    // Named scratchpads spawned and waiting for their window to map
+    pending_scratchpads: RefCell<Vec<PendingScratchpad>>,
+    // Programs started for a workspace, until their first window maps
+    pending_spawns: RefCell<Vec<PendingSpawn>>,
+    // Number of startup notification ids handed out, to keep them unique
+    startup_sequence: RefCell<u32>,
+    // Commands of the spawned programs still running, by process id
+    children: RefCell<HashMap<u32, String>>,
+    // Display the WM connected to, passed on to spawned programs
+    display: String,
+    ipc: Ipc,
+    signals: RefCell<Signals>,
 }
 
 impl WM {
-    pub fn new(config: Config) -> Result<Self> {
+    pub fn new(config: Config, config_path: Option<String>) -> Result<Self> {
         let (conn, screen_num) = x11rb::connect(None).map_err(Error::from)?;
 
         let atom_requests = Atom::iter()
@@ -88,11 +258,42 @@ impl WM {
             .unwrap()
             .check()
             .unwrap();
-        let commands = Self::build_command_map(config.get_custom_commands());
         let screen = conn.setup().roots.get(screen_num).unwrap();
+        let has_randr = conn
+            .randr_query_version(1, 3)
+            .map_err(Error::from)
+            .and_then(|cookie| cookie.reply().map_err(Error::from))
+            .is_ok();
+        let outputs = if has_randr {
+            connected_outputs(&conn, screen.root)?
+        } else {
+            Vec::new()
+        };
+        // the config was loaded before the outputs were known
+        let config = if config.depends_on_outputs() {
+            config::load_config(config_path.as_deref(), &ConfigContext::new(outputs.clone()))
+                .map_err(|e| Error::Config(e.to_string()))?
+        } else {
+            config
+        };
+        let commands = Self::build_command_map(config.get_custom_commands());
         let width = screen.width_in_pixels as u32;
         let height = screen.height_in_pixels as u32;
-        let wm_state = WmState::new(1, width, height);
+        let mut wm_state = WmState::new(NUM_WORKSPACES, width, height);
+        if has_randr {
+            // no window is managed yet, there are no frames to show or hide
+            wm_state.set_outputs(&output_areas(&conn, screen.root)?);
+        }
+        let xkb_version = conn.xkb_use_extension(1, 0)?.reply()?;
+        if !xkb_version.supported {
+            return Err(Error::Unknown(format!(
+                "XKB 1.0 is not supported, the server has {}.{}",
+                xkb_version.server_major, xkb_version.server_minor
+            )));
+        }
+        let keyboard = Keyboard::new()?;
+        let ipc = Ipc::bind()?;
+        let signals = Signals::new()?;
         Ok(Self {
             atoms,
             conn,
@@ -100,16 +301,53 @@ impl WM {
             window_frame_map: RefCell::new(HashMap::new()),
             screen_num,
             running: RefCell::new(false),
-            focusing_client: RefCell::new(None),
+            focus_history: RefCell::new(Vec::new()),
+            previous_workspace: RefCell::new(None),
+            focus_cycle: RefCell::new(None),
             normal_cursor,
-            display_stack: RefCell::new(VecDeque::new()),
-            config,
-            commands,
+            root_cursor: RefCell::new(NONE),
+            busy_cursor: RefCell::new(NONE),
+            drag_cursor: RefCell::new(NONE),
+            config: RefCell::new(config),
+            config_path,
+            pending_config: RefCell::new(None),
+            config_watcher: RefCell::new(None),
+            reload_deadline: RefCell::new(None),
+            has_randr,
+            outputs: RefCell::new(outputs),
+            commands: RefCell::new(commands),
             wm_state: RefCell::new(wm_state),
-            wm_mode: "default".into(),
+            wm_mode: RefCell::new(DEFAULT_MODE.into()),
+            mode_deadline: RefCell::new(None),
+            pending_chord: RefCell::new(None),
+            button_press: RefCell::new(None),
+            drag: RefCell::new(None),
+            release_commands: RefCell::new(HashMap::new()),
+            keyboard: RefCell::new(keyboard),
+            frame_geometries: RefCell::new(HashMap::new()),
+            ignore_enter_until: RefCell::new(0),
+            lock_masks: RefCell::new((0, 0)),
+            last_timestamp: RefCell::new(CURRENT_TIME),
+            applied_rules: RefCell::new(HashMap::new()),
+            urgency_hints: RefCell::new(HashSet::new()),
+            demanding_attention: RefCell::new(HashSet::new()),
+            pending_scratchpads: RefCell::new(Vec::new()),
+            pending_spawns: RefCell::new(Vec::new()),
+            startup_sequence: RefCell::new(0),
+            children: RefCell::new(HashMap::new()),
+            display: std::env::var("DISPLAY").unwrap_or_default(),
+            ipc,
+            signals: RefCell::new(signals),
         })
     }
 
+    /// Names of every command a config can bind, built in or custom.
+    pub fn command_names(config: &Config) -> Vec<String> {
+        Self::build_command_map(config.get_custom_commands())
+            .into_keys()
+            .collect()
+    }
+
     fn build_command_map<'a>(
         custom_commands: Option<&'a HashMap<String, String>>,
     ) -> HashMap<String, Handler> {
@@ -118,10 +356,7 @@ impl WM {
                 .iter()
                 .map(|(k, v)| {
                     let v = v.clone();
-                    let handler: Handler = Box::new(move |_wm| {
-                        WM::spawn(&v);
-                        Ok(())
-                    });
+                    let handler: Handler = Box::new(move |wm, _| wm.spawn(&v, None));
                     (k.clone(), handler)
                 })
                 .collect()
@@ -130,61 +365,189 @@ impl WM {
         };
         map.insert(
             "quit".into(),
-            Box::new(|_| {
+            Box::new(|_, _| {
                 exit(0);
             }),
         );
         map.insert(
-            "focus_left".into(),
-            Box::new(|wm| {
-                wm.focus_left();
-                Ok(())
+            "focus".into(),
+            Box::new(|wm, args| match args {
+                ["parent"] => wm.focus_parent(),
+                ["child"] => wm.focus_child(),
+                ["urgent"] => wm.focus_urgent(),
+                ["next"] => wm.cycle_focus(true),
+                ["prev"] => wm.cycle_focus(false),
+                [direction] => wm.focus_in_direction(parse_argument("focus", direction)?),
+                _ => Err(Error::InvalidArguments("focus".into(), args.join(" "))),
+            }),
+        );
+        map.insert(
+            "move".into(),
+            Box::new(|wm, args| match args {
+                ["workspace", number] => wm.move_to_workspace(parse_workspace("move", number)?),
+                ["scratchpad"] => wm.move_to_scratchpad(),
+                [] => wm.start_drag(DragKind::Move),
+                [direction] => wm.move_in_direction(parse_argument("move", direction)?),
+                _ => Err(Error::InvalidArguments("move".into(), args.join(" "))),
             }),
         );
         map.insert(
-            "focus_right".into(),
-            Box::new(|wm| {
-                wm.focus_right();
+            "layout".into(),
+            Box::new(|wm, args| {
+                let mut wm_state = wm.wm_state.borrow_mut();
+                match args {
+                    ["toggle"] => wm_state.toggle_layout(),
+                    // tabbed and floating containers are not laid out yet
+                    ["horizontal"] => wm_state.change_layout(LayoutType::Horizontal),
+                    ["vertical"] => wm_state.change_layout(LayoutType::Vertical),
+                    _ => return Err(Error::InvalidArguments("layout".into(), args.join(" "))),
+                }
                 Ok(())
             }),
         );
         map.insert(
-            "terminal".into(),
-            Box::new(|_| {
-                WM::spawn("alacritty");
+            "split".into(),
+            Box::new(|wm, args| {
+                let layout_type = match args {
+                    ["horizontal"] => LayoutType::Horizontal,
+                    ["vertical"] => LayoutType::Vertical,
+                    _ => return Err(Error::InvalidArguments("split".into(), args.join(" "))),
+                };
+                wm.wm_state.borrow_mut().split(layout_type);
                 Ok(())
             }),
         );
         map.insert(
-            "close_window".into(),
-            Box::new(|wm| {
-                // let top_of_stack = wm.display_stack.borrow_mut().pop_back();
-                let wm_state = wm.wm_state.borrow();
-                let focusing_container = wm_state.get_focusing_container();
-                if let Some(container) = focusing_container {
-                    if let Some(window_id) = container.main_win_id {
-                        wm.conn.kill_client(window_id).unwrap();
-                    } else {
-                        wm.conn
-                            .kill_client(container.frame_win_id.unwrap())
-                            .unwrap();
+            "floating".into(),
+            Box::new(|wm, args| {
+                {
+                    let mut wm_state = wm.wm_state.borrow_mut();
+                    let focus_history = wm.focus_history.borrow();
+                    match args {
+                        ["enable"] => wm_state.set_floating(true, &focus_history),
+                        ["disable"] => wm_state.set_floating(false, &focus_history),
+                        ["toggle"] => wm_state.toggle_floating(&focus_history),
+                        _ => {
+                            return Err(Error::InvalidArguments("floating".into(), args.join(" ")))
+                        }
                     }
                 }
+                wm.raise_floating_frames();
                 Ok(())
             }),
         );
+        map.insert(
+            "resize".into(),
+            Box::new(|wm, args| match args {
+                ["set", width, height] => {
+                    wm.wm_state.borrow_mut().resize_floating(
+                        parse_argument("resize", width)?,
+                        parse_argument("resize", height)?,
+                    );
+                    Ok(())
+                }
+                [] => wm.start_drag(DragKind::Resize),
+                _ => Err(Error::InvalidArguments("resize".into(), args.join(" "))),
+            }),
+        );
+        map.insert(
+            "scratchpad".into(),
+            Box::new(|wm, args| match args {
+                ["show", criteria @ ..] => {
+                    let criteria = WindowCriteria::parse(criteria)
+                        .map_err(|e| Error::InvalidArguments("scratchpad".into(), e))?;
+                    wm.show_scratchpad(&criteria)
+                }
+                ["toggle", name] => wm.toggle_named_scratchpad(name),
+                _ => Err(Error::InvalidArguments("scratchpad".into(), args.join(" "))),
+            }),
+        );
+        map.insert(
+            "exec".into(),
+            // run_command passes the command line unsplit instead
+            Box::new(|wm, args| wm.exec(&args.join(" "))),
+        );
+        map.insert(
+            "terminal".into(),
+            Box::new(|wm, _| wm.spawn("alacritty", None)),
+        );
+        map.insert(
+            "workspace".into(),
+            Box::new(|wm, args| match args {
+                ["back_and_forth"] => wm.workspace_back_and_forth(),
+                ["prev"] => {
+                    wm.switch_workspace(wm.adjacent_workspace(false));
+                    Ok(())
+                }
+                ["next"] => {
+                    wm.switch_workspace(wm.adjacent_workspace(true));
+                    Ok(())
+                }
+                [number] => {
+                    wm.switch_workspace(parse_workspace("workspace", number)?);
+                    Ok(())
+                }
+                _ => Err(Error::InvalidArguments("workspace".into(), args.join(" "))),
+            }),
+        );
+        map.insert("kill".into(), Box::new(|wm, _| wm.kill_focused()));
+        map.insert("close_window".into(), Box::new(|wm, _| wm.kill_focused()));
+        map.insert("reload".into(), Box::new(|wm, _| wm.reload_config()));
+        map.insert(
+            "mode".into(),
+            Box::new(|wm, args| match args {
+                [mode] => wm.set_mode(mode),
+                _ => Err(Error::InvalidArguments("mode".into(), args.join(" "))),
+            }),
+        );
 
         map
     }
 
+    fn kill_focused(&self) -> Result<()> {
+        let wm_state = self.wm_state.borrow();
+        let focusing_container = wm_state.get_focusing_container();
+        if let Some(container) = focusing_container {
+            // a focused split takes every window in it along
+            for window_id in container.leaves().iter().filter_map(|c| c.main_win_id) {
+                self.conn.kill_client(window_id).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a command string such as `mode resize`.
+    /// The first word names the command, the rest are passed as its arguments.
+    pub fn run_command(&self, command: &str) -> Result<()> {
+        let command = command.trim();
+        let (name, rest) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        if name.is_empty() {
+            return Ok(());
+        }
+        // the shell gets the command line as written, quotes and spacing included
+        if name == "exec" {
+            return self.exec(rest.trim_start());
+        }
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let commands = self.commands.borrow();
+        let handler = commands
+            .get(name)
+            .ok_or_else(|| Error::UnknownCommand(name.into()))?;
+        handler(self, &args)
+    }
+
     pub fn init(&self) {
         let attrs = ChangeWindowAttributesAux::default().event_mask(
             EventMask::SUBSTRUCTURE_REDIRECT
                 | EventMask::SUBSTRUCTURE_NOTIFY
+                | EventMask::ENTER_WINDOW
                 | EventMask::BUTTON_PRESS
                 | EventMask::BUTTON_RELEASE
                 | EventMask::KEY_PRESS
-                | EventMask::KEY_RELEASE,
+                | EventMask::KEY_RELEASE
+                | EventMask::PROPERTY_CHANGE,
         );
 
         self.conn
@@ -192,6 +555,24 @@ impl WM {
             .unwrap()
             .check()
             .unwrap();
+        // the PropertyNotify of an empty append carries the current server time,
+        // until then there is no valid timestamp for WM_TAKE_FOCUS
+        self.conn
+            .change_property8(
+                PropMode::APPEND,
+                self.screen().root,
+                self.atoms[&Atom::_LAZYWM_TIMESTAMP],
+                AtomEnum::STRING,
+                &[],
+            )
+            .unwrap();
+        self.advertise_supported();
+        self.warn_config_problems(&self.config.borrow());
+        self.select_xkb_events();
+        self.select_randr_events();
+        self.update_lock_masks();
+        self.update_config_watcher();
+        self.grab_keys(&self.wm_mode.borrow());
 
         self.conn.grab_server().unwrap().check().unwrap();
         let tree = self
@@ -211,6 +592,10 @@ impl WM {
             .reply()
             .unwrap();
         let left_ptr = cursor_handler.load_cursor(&self.conn, "left_ptr").unwrap();
+        let watch = cursor_handler.load_cursor(&self.conn, "watch").unwrap();
+        *self.root_cursor.borrow_mut() = left_ptr;
+        *self.busy_cursor.borrow_mut() = watch;
+        *self.drag_cursor.borrow_mut() = cursor_handler.load_cursor(&self.conn, "fleur").unwrap();
 
         self.conn
             .change_window_attributes(
@@ -223,248 +608,1726 @@ impl WM {
         self.conn.flush().unwrap();
     }
 
+    /// List the EWMH hints the WM handles in `_NET_SUPPORTED` of the root window.
+    fn advertise_supported(&self) {
+        let supported = [
+            Atom::_NET_SUPPORTED,
+            Atom::_NET_ACTIVE_WINDOW,
+            Atom::_NET_WM_STATE,
+            Atom::_NET_WM_STATE_DEMANDS_ATTENTION,
+            Atom::_NET_WM_NAME,
+            Atom::_NET_WM_WINDOW_TYPE,
+            Atom::_NET_WM_PID,
+        ]
+        .map(|atom| self.atoms[&atom]);
+        self.conn
+            .change_property32(
+                PropMode::REPLACE,
+                self.screen().root,
+                self.atoms[&Atom::_NET_SUPPORTED],
+                AtomEnum::ATOM,
+                &supported,
+            )
+            .unwrap();
+    }
+
+    /// Start the autostart programs, unless they ran in this X session already,
+    /// then the autostart_always ones. Called once the screen is managed,
+    /// so that rules apply to their windows.
+    pub fn autostart(&self) {
+        let root = self.screen().root;
+        let atom = self.atoms[&Atom::_LAZYWM_AUTOSTARTED];
+        let started = self
+            .conn
+            .get_property(false, root, atom, AtomEnum::ANY, 0, 0)
+            .unwrap()
+            .reply()
+            .is_ok_and(|reply| reply.type_ != NONE);
+        let (autostart, autostart_always) = {
+            let config = self.config.borrow();
+            (
+                config.get_autostart().to_vec(),
+                config.get_autostart_always().to_vec(),
+            )
+        };
+        if !started {
+            self.start_programs(&autostart);
+            self.conn
+                .change_property8(PropMode::REPLACE, root, atom, AtomEnum::STRING, &[])
+                .unwrap();
+        }
+        self.start_programs(&autostart_always);
+        self.conn.flush().unwrap();
+    }
+
+    fn start_programs(&self, programs: &[Autostart]) {
+        for program in programs {
+            let workspace = match program.workspace.as_deref() {
+                Some(workspace) => match parse_workspace("autostart", workspace) {
+                    Ok(index) => Some(index),
+                    Err(e) => {
+                        warn!("Not starting {}: {}", program.command, e);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            info!("Starting {}", program.command);
+            if let Err(e) = self.spawn(&program.command, workspace) {
+                warn!("{}", e);
+            }
+        }
+    }
+
     pub fn run(&self) {
         let conn = &self.conn;
         {
             *self.running.borrow_mut() = true;
         }
         while *self.running.borrow() {
+            loop {
+                match conn.poll_for_event_with_sequence() {
+                    Ok(Some((event, sequence))) => self.handle_event(event, sequence),
+                    Ok(None) => break,
+                    Err(_) => return,
+                }
+            }
+            self.handle_timeouts();
+            for request in self.ipc.dispatch() {
+                let result = self.run_command(&request.command);
+                self.ipc
+                    .reply(request.client, result.map_err(|e| e.to_string()));
+                self.configure_repositioned_windows();
+            }
+            for signal in self.signals.borrow_mut().dispatch() {
+                match signal {
+                    Signal::SIGHUP => {
+                        let _ = self.reload_config();
+                    }
+                    Signal::SIGCHLD => self.reap_children(),
+                    _ => {}
+                }
+            }
+            let config_changed = self
+                .config_watcher
+                .borrow()
+                .as_ref()
+                .is_some_and(|w| w.dispatch());
+            if config_changed {
+                *self.reload_deadline.borrow_mut() = Some(Instant::now() + CONFIG_RELOAD_DELAY);
+            }
+            self.apply_pending_config();
             conn.flush().unwrap();
-            let Ok(event) = conn.wait_for_event() else {
-                break
-            };
+            self.wait_for_activity();
+        }
+    }
 
-            match event {
-                Event::MapRequest(xev) => self.handle_map_request(xev),
-                Event::ConfigureRequest(xev) => self.handle_configure_request(xev),
-                Event::UnmapNotify(xev) => self.handle_unmap_notify(xev),
-                Event::KeyPress(xev) => self.handle_key_press(xev),
-                Event::ButtonPress(xev) => self.handle_button_press(xev),
-                Event::FocusIn(xev) => self.handle_focus_in(xev),
-                Event::FocusOut(xev) => self.handle_focus_out(xev),
-                Event::EnterNotify(xev) => self.handle_enter_window(xev),
-                _ => {}
-            }
-
-            let binding = self.wm_state.borrow_mut();
-            let repositioned_windows = binding.get_repositioned_containers();
-            repositioned_windows.iter().for_each(|w| {
-                let c = *w;
-                let (width, height) = c.get_dimensions();
-                let (x, y) = c.get_position();
+    /// Block until the X connection or an IPC client has something to read,
+    /// or until the nearest pending timeout expires.
+    fn wait_for_activity(&self) {
+        let mut fds = vec![PollFd::new(
+            self.conn.stream().as_raw_fd(),
+            PollFlags::POLLIN,
+        )];
+        fds.push(PollFd::new(self.signals.borrow().fd(), PollFlags::POLLIN));
+        if let Some(watcher) = self.config_watcher.borrow().as_ref() {
+            fds.push(PollFd::new(watcher.fd(), PollFlags::POLLIN));
+        }
+        fds.extend(
+            self.ipc
+                .fds()
+                .into_iter()
+                .map(|fd| PollFd::new(fd, PollFlags::POLLIN)),
+        );
+        let timeout = match self.next_deadline() {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis()
+                .min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+        if let Err(e) = poll(&mut fds, timeout) {
+            warn!("poll failed: {}", e);
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let chord_deadline = self.pending_chord.borrow().as_ref().map(|c| c.deadline);
+        [
+            *self.mode_deadline.borrow(),
+            chord_deadline,
+            *self.reload_deadline.borrow(),
+            self.pending_spawns
+                .borrow()
+                .iter()
+                .map(|s| s.deadline)
+                .min(),
+            self.pending_scratchpads
+                .borrow()
+                .iter()
+                .map(|s| s.deadline)
+                .min(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    fn handle_timeouts(&self) {
+        let now = Instant::now();
+        let chord_expired =
+            matches!(self.pending_chord.borrow().as_ref(), Some(c) if c.deadline <= now);
+        if chord_expired {
+            info!("Key sequence timed out");
+            self.end_chord();
+        }
+        let mode_expired = matches!(*self.mode_deadline.borrow(), Some(d) if d <= now);
+        if mode_expired {
+            info!("Mode {} timed out", self.wm_mode.borrow());
+            self.mode_deadline.borrow_mut().take();
+            if let Err(e) = self.set_mode(DEFAULT_MODE) {
+                warn!("Cannot leave mode {}: {}", self.wm_mode.borrow(), e);
+            }
+        }
+        let reload_expired = matches!(*self.reload_deadline.borrow(), Some(d) if d <= now);
+        if reload_expired {
+            self.reload_deadline.borrow_mut().take();
+            info!("Config file changed");
+            // failures are logged and broadcasted
+            let _ = self.reload_config();
+        }
+        let spawns_expired = self
+            .pending_spawns
+            .borrow()
+            .iter()
+            .any(|s| s.deadline <= now);
+        if spawns_expired {
+            self.pending_spawns
+                .borrow_mut()
+                .retain(|s| s.deadline > now);
+            self.update_busy_cursor();
+        }
+        self.pending_scratchpads.borrow_mut().retain(|s| {
+            if s.deadline <= now {
+                info!("Scratchpad {} showed no window in time", s.name);
+            }
+            s.deadline > now
+        });
+    }
+
+    fn handle_event(&self, event: Event, sequence: SequenceNumber) {
+        if let Some(time) = event_time(&event) {
+            *self.last_timestamp.borrow_mut() = time;
+        }
+        match event {
+            Event::EnterNotify(_) if sequence <= *self.ignore_enter_until.borrow() => {}
+            Event::MapRequest(xev) => self.handle_map_request(xev),
+            Event::ConfigureRequest(xev) => self.handle_configure_request(xev),
+            Event::UnmapNotify(xev) => self.handle_unmap_notify(xev),
+            Event::KeyPress(xev) => self.handle_key_press(xev),
+            Event::KeyRelease(xev) => self.handle_key_release(xev),
+            Event::ButtonPress(xev) => self.handle_button_press(xev),
+            Event::ButtonRelease(xev) => self.handle_button_release(xev),
+            Event::MotionNotify(xev) => self.handle_motion_notify(xev),
+            Event::FocusIn(xev) => self.handle_focus_in(xev),
+            Event::FocusOut(xev) => self.handle_focus_out(xev),
+            Event::EnterNotify(xev) => self.handle_enter_window(xev),
+            Event::MappingNotify(xev) => self.handle_mapping_notify(xev),
+            Event::PropertyNotify(xev) => self.handle_property_notify(xev),
+            Event::ClientMessage(xev) => self.handle_client_message(xev),
+            Event::XkbStateNotify(xev) => self.keyboard.borrow_mut().update_state(&xev),
+            Event::XkbNewKeyboardNotify(_) | Event::XkbMapNotify(_) => self.reload_keyboard(),
+            Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_) => {
+                self.handle_output_change()
+            }
+            _ => {}
+        }
+        self.configure_repositioned_windows();
+    }
+
+    fn configure_repositioned_windows(&self) {
+        let binding = self.wm_state.borrow_mut();
+        let repositioned_windows = binding.get_repositioned_containers();
+        let mut frame_geometries = self.frame_geometries.borrow_mut();
+        let mut last_sequence = None;
+        repositioned_windows.iter().for_each(|w| {
+            let c = *w;
+            let (width, height) = c.get_dimensions();
+            let (x, y) = c.get_position();
+            let frame_win = w.frame_win_id.unwrap();
+            if frame_geometries.get(&frame_win) == Some(&(x, y, width, height)) {
+                return;
+            }
+            frame_geometries.insert(frame_win, (x, y, width, height));
+            let (width, height) = inner_size(width, height);
+            let cookie = self
+                .conn
+                .configure_window(
+                    frame_win,
+                    &ConfigureWindowAux::new()
+                        .width(width)
+                        .height(height)
+                        .x(x as i32)
+                        .y(y as i32),
+                )
+                .unwrap();
+            last_sequence = Some(cookie.sequence_number());
+            if let Some(main_win_id) = w.main_win_id {
                 self.conn
                     .configure_window(
-                        w.frame_win_id.unwrap(),
+                        main_win_id,
                         &ConfigureWindowAux::new()
                             .width(width)
                             .height(height)
-                            .x(x as i32)
-                            .y(y as i32),
+                            .x(0)
+                            .y(0),
                     )
                     .unwrap();
-                if let Some(main_win_id) = w.main_win_id {
-                    self.conn
-                        .configure_window(
-                            main_win_id,
-                            &ConfigureWindowAux::new()
-                                .width(width)
-                                .height(height)
-                                .x(0)
-                                .y(0),
-                        )
-                        .unwrap();
-                }
-            });
+            }
+        });
+        if let Some(sequence) = last_sequence {
+            self.ignore_enter_notify(sequence);
         }
     }
 
+    /// Ignore the EnterNotify events generated up to the request `sequence`,
+    /// they come from windows moving under the pointer rather than the pointer moving.
+    fn ignore_enter_notify(&self, sequence: SequenceNumber) {
+        *self.ignore_enter_until.borrow_mut() = sequence;
+        // Crossings caused by later pointer motion carry the sequence number of the
+        // last processed request. Send one more so that they are not mistaken for ours.
+        drop(self.conn.get_input_focus().unwrap());
+    }
+
     fn screen(&self) -> &Screen {
         self.conn.setup().roots.get(self.screen_num).unwrap()
     }
 
     fn handle_enter_window(&self, event: EnterNotifyEvent) {
+        let policy = self.config.borrow().get_focus_policy();
+        // crossings caused by grabs, e.g. while a key sequence is pending, are not pointer moves
+        if policy == FocusPolicy::Click || event.mode != NotifyMode::NORMAL {
+            return;
+        }
+
+        if event.event == self.screen().root {
+            // from a frame back to the desktop
+            if policy == FocusPolicy::FollowsMouse && event.detail == NotifyDetail::INFERIOR {
+                self.unfocus();
+            }
+            return;
+        }
         if !self.window_frame_map.borrow().contains_key(&event.event) {
             return;
         }
         info!("Enter window: {:#?}", event.event);
-        info!(
-            "Set focus to: {} - frame: {:#?} - main: {:#?}",
-            event.event,
-            self.window_frame_map.borrow().get(&event.event),
-            self.window_frame_map
-                .borrow()
-                .values()
-                .find(|&&v| v == event.event)
-        );
+        self.focus_window(event.event, false);
+    }
 
+    /// Focus a client, both in the WM state and for X input, showing its workspace.
+    /// The pointer is only warped for focus changes that did not come from it.
+    fn focus_window(&self, client_win: Window, warp: bool) {
+        let workspace = self.wm_state.borrow().find_workspace(client_win);
+        if let Some(workspace) = workspace {
+            self.show_workspace(workspace);
+        }
         self.wm_state
             .borrow_mut()
-            .set_focusing_container(event.event);
+            .set_focusing_container(client_win);
+        self.apply_focus(warp);
     }
 
-    fn handle_map_request(&self, event: MapRequestEvent) {
-        let client_win = event.window;
-        self.frame(client_win, false);
+    fn unfocus(&self) {
+        self.wm_state.borrow_mut().unset_focusing_container();
+        self.apply_focus(false);
     }
 
-    fn frame(&self, client_win: Window, scanning: bool) {
-        let conn = &self.conn;
-        let screen = &conn.setup().roots[self.screen_num];
-        let client_win_geometry = conn.get_geometry(client_win).unwrap().reply().unwrap();
-        let client_win_attrs = conn
-            .get_window_attributes(client_win)
-            .unwrap()
-            .reply()
-            .unwrap();
+    /// Give the X input focus to the container focused in the WM state,
+    /// and highlight its frame, or the frames of every window in a focused split.
+    fn apply_focus(&self, warp: bool) {
+        // rule actions focus their window on its workspace only, which may not be shown
+        if self.wm_state.borrow().has_command_target() {
+            return;
+        }
+        let focused = {
+            let wm_state = self.wm_state.borrow();
+            wm_state.get_focusing_container().and_then(|c| {
+                let (width, height) = c.get_dimensions();
+                let (x, y) = c.get_position();
+                Some((wm_state.get_focused_window()?, x, y, width, height))
+            })
+        };
+        let Some((client_win, x, y, width, height)) = focused else {
+            self.update_frame_borders();
+            self.set_input_focus(self.screen().root);
+            return;
+        };
 
-        if scanning {
-            if client_win_attrs.override_redirect
-                || client_win_attrs.map_state != MapState::VIEWABLE
-            {
-                return;
-            }
+        // the window got the attention it asked for
+        self.clear_urgency(client_win);
+        self.update_frame_borders();
+        self.focus_client(client_win);
+        // windows only previewed while cycling do not count as focused
+        if self.focus_cycle.borrow().is_none() {
+            self.record_focus(client_win);
+        }
+        if warp && self.config.borrow().get_mouse_warping() {
+            // relative to the root, the frame may not be at its new place yet
+            self.conn
+                .warp_pointer(
+                    NONE,
+                    self.screen().root,
+                    0,
+                    0,
+                    0,
+                    0,
+                    (x + width / 2) as i16,
+                    (y + height / 2) as i16,
+                )
+                .unwrap();
         }
+    }
 
-        let frame_win: Window = conn.generate_id().unwrap();
+    /// Move a window to the front of the focus history.
+    fn record_focus(&self, client_win: Window) {
+        let mut focus_history = self.focus_history.borrow_mut();
+        focus_history.retain(|&w| w != client_win);
+        focus_history.insert(0, client_win);
+    }
 
-        // let config = ConfigureWindowAux::new()
-        //     .width(self.screen().width_in_pixels as u32)
-        //     .height(self.screen().height_in_pixels as u32);
-        // conn.configure_window(client_win, &config)
-        //     .unwrap()
-        //     .check()
-        //     .unwrap();
-        let attrs = CreateWindowAux::new()
-            .background_pixel(screen.black_pixel)
-            .border_pixel(screen.black_pixel)
-            .override_redirect(1)
-            .event_mask(EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY);
+    /// Switch to a workspace and focus its last focused window.
+    fn switch_workspace(&self, index: usize) {
+        self.show_workspace(index);
+        self.apply_focus(false);
+    }
+
+    /// The workspace before or after the current one, wrapping around.
+    fn adjacent_workspace(&self, forward: bool) -> usize {
+        let current = self.wm_state.borrow().get_current_workspace_index();
+        if forward {
+            (current + 1) % NUM_WORKSPACES
+        } else {
+            (current + NUM_WORKSPACES - 1) % NUM_WORKSPACES
+        }
+    }
 
+    /// Map the frames of a workspace in place of the ones of the current workspace, or
+    /// focus the output already showing it.
+    fn show_workspace(&self, index: usize) {
         let mut wm_state = self.wm_state.borrow_mut();
-        let new_container = wm_state.new_container(client_win, frame_win);
-        let (width, height) = new_container.get_dimensions();
-        let (x, y) = new_container.get_position();
-        conn.create_window(
-            screen.root_depth,
-            frame_win,
-            screen.root,
-            x as i16,
-            y as i16,
-            width as u16,
-            height as u16,
-            client_win_geometry.border_width,
-            client_win_attrs.class,
-            screen.root_visual,
-            &attrs,
-        )
-        .unwrap();
-        conn.change_save_set(SetMode::INSERT, client_win).unwrap();
-        conn.reparent_window(client_win, frame_win, 0, 0).unwrap();
-        conn.map_window(frame_win).unwrap();
+        if wm_state.get_current_workspace_index() == index {
+            return;
+        }
+        *self.previous_workspace.borrow_mut() = Some(wm_state.get_current_workspace_index());
+        if wm_state.is_workspace_shown(index) {
+            wm_state.change_workspace(index);
+            info!("Switched to workspace {} on another output", index + 1);
+            return;
+        }
+        for frame_win in wm_state.get_current_frames() {
+            self.conn.unmap_window(frame_win).unwrap();
+        }
+        wm_state.change_workspace(index);
+        let frames = wm_state.get_current_frames();
+        drop(wm_state);
+        self.map_frames(&frames);
+        self.raise_floating_frames();
+        info!("Switched to workspace {}", index + 1);
+    }
 
-        self.grab_buttons(frame_win);
-        self.grab_keys(frame_win, "default");
-        let config = ChangeWindowAttributesAux::default()
-            .event_mask(EventMask::ENTER_WINDOW | EventMask::FOCUS_CHANGE);
-        conn.change_window_attributes(client_win, &config).unwrap();
-        conn.map_window(client_win).unwrap();
-        conn.set_input_focus(InputFocus::POINTER_ROOT, client_win, CURRENT_TIME)
-            .unwrap()
-            .check()
-            .unwrap();
-        self.window_frame_map
-            .borrow_mut()
-            .insert(client_win, frame_win);
+    /// Map frames, the windows appearing under the pointer do not take the focus.
+    fn map_frames(&self, frames: &[Window]) {
+        let mut last_sequence = None;
+        for &frame_win in frames {
+            let cookie = self.conn.map_window(frame_win).unwrap();
+            last_sequence = Some(cookie.sequence_number());
+        }
+        if let Some(sequence) = last_sequence {
+            self.ignore_enter_notify(sequence);
+        }
     }
 
-    fn handle_configure_request(&self, event: ConfigureRequestEvent) {
-        let conn = &self.conn;
-        let configure_attrs = ConfigureWindowAux::from_configure_request(&event);
+    /// Go back to the workspace shown before the current one, even if it is empty.
+    fn workspace_back_and_forth(&self) -> Result<()> {
+        let previous = *self.previous_workspace.borrow();
+        if let Some(workspace) = previous {
+            self.switch_workspace(workspace);
+        }
+        Ok(())
+    }
 
-        if let Some(client) = self.clients.borrow().get(&event.window) {
-            conn.configure_window(client.frame_win, &configure_attrs)
-                .unwrap();
+    /// Focus the next (older) or previous (newer) window of the focus history.
+    /// While the modifiers of the binding are held, the history is left as is so
+    /// that repeated presses walk further back. Releasing them commits the focus.
+    /// Windows hidden in the scratchpad or no longer mapped are passed over.
+    fn cycle_focus(&self, forward: bool) -> Result<()> {
+        let mut cycle = self.focus_cycle.borrow_mut().take();
+        if cycle.is_none() {
+            let windows: Vec<Window> = self
+                .focus_history
+                .borrow()
+                .iter()
+                .copied()
+                .filter(|&w| self.is_on_workspace(w))
+                .collect();
+            if windows.len() < 2 {
+                return Ok(());
+            }
+            if self.modifiers_held() && self.grab_keyboard() {
+                cycle = Some(FocusCycle { windows, index: 0 });
+            } else {
+                // from IPC, or without a way to see the modifiers go: a single step
+                let window = if forward {
+                    windows[1]
+                } else {
+                    windows[windows.len() - 1]
+                };
+                self.focus_window(window, true);
+                return Ok(());
+            }
         }
 
-        conn.configure_window(event.window, &configure_attrs)
-            .unwrap();
+        let mut cycle = cycle.unwrap();
+        let len = cycle.windows.len();
+        // windows hidden or closed since the cycle started are skipped
+        for _ in 0..len {
+            cycle.index = if forward {
+                (cycle.index + 1) % len
+            } else {
+                (cycle.index + len - 1) % len
+            };
+            if self.is_on_workspace(cycle.windows[cycle.index]) {
+                break;
+            }
+        }
+        let window = cycle.windows[cycle.index];
+        *self.focus_cycle.borrow_mut() = Some(cycle);
+        self.focus_window(window, true);
+        Ok(())
     }
 
-    fn grab_buttons(&self, _window: Window) {
-        // self.conn
-        //     .grab_button(
-        //         false,
-        //         _window,
-        //         EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE,
-        //         GrabMode::ASYNC,
-        //         GrabMode::ASYNC,
-        //         self.screen().root,
-        //         self.normal_cursor,
-        //         ButtonIndex::ANY,
-        //         ModMask::from(self.config.get_mod_mask() as u16),
-        //     )
-        //     .unwrap()
-        //     .check()
-        //     .unwrap();
+    /// Whether a client is managed and on a workspace, rather than hidden in the scratchpad.
+    fn is_on_workspace(&self, client_win: Window) -> bool {
+        self.window_frame_map.borrow().contains_key(&client_win)
+            && self.wm_state.borrow().find_workspace(client_win).is_some()
     }
 
-    fn grab_keys(&self, _window: Window, mode: &str) {
-        let conn = &self.conn;
-        let setup = conn.setup();
-        let max_keycode = setup.max_keycode;
-        let min_keycode = setup.min_keycode;
+    fn end_focus_cycle(&self) {
+        let Some(cycle) = self.focus_cycle.borrow_mut().take() else {
+            return;
+        };
+        self.conn.ungrab_keyboard(CURRENT_TIME).unwrap();
+        if let Some(client_win) = self.wm_state.borrow().get_focused_window() {
+            self.record_focus(client_win);
+        }
+        info!("Focus cycle ended on window {}", cycle.windows[cycle.index]);
+    }
 
-        let keymap = conn
-            .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)
+    /// Whether any modifier, other than the locks, is held down.
+    fn modifiers_held(&self) -> bool {
+        let pointer = self
+            .conn
+            .query_pointer(self.screen().root)
             .unwrap()
             .reply()
             .unwrap();
-        let keysyms_per_keycode = keymap.keysyms_per_keycode as usize;
-
-        //(K - first_code) * keysyms_per_code_return + N
-        let config_key_map = self.config.get_key_maps(mode).expect("invalid mode");
+        self.clean_mask(u32::from(pointer.mask) & 0xff) != 0
+    }
 
-        for k in min_keycode..=max_keycode {
-            let idx = ((k - min_keycode) as usize) * keysyms_per_keycode;
-            let keysym = keymap.keysyms[idx];
+    /// The modifier bit a key is mapped to, 0 for keys that are not modifiers.
+    fn modifier_mask_of(&self, keycode: Keycode) -> u32 {
+        let modifier_mapping = self.conn.get_modifier_mapping().unwrap().reply().unwrap();
+        let keycodes_per_modifier = modifier_mapping.keycodes_per_modifier() as usize;
+        modifier_mapping
+            .keycodes
+            .iter()
+            .position(|&k| k != 0 && k as Keycode == keycode)
+            .map(|i| 1 << (i / keycodes_per_modifier))
+            .unwrap_or(0)
+    }
 
-            if config_key_map.contains_key(&keysym) {
-                let entry = &config_key_map[&keysym];
-                for (mod_mask, _) in entry {
-                    let mod_mask = mod_mask | self.config.get_mod_mask();
-                    conn.grab_key(
-                        false,
-                        _window,
-                        ModMask::from(mod_mask as u16),
-                        k,
+    /// Color the frame of the focused container, or the frames of every window
+    /// in a focused split, and the frames of the windows that need attention.
+    fn update_frame_borders(&self) {
+        let (focused_frames, urgent_frames) = {
+            let wm_state = self.wm_state.borrow();
+            let focused_frames: Vec<Window> = wm_state
+                .get_focusing_container()
+                .map(|c| c.leaves().iter().filter_map(|c| c.frame_win_id).collect())
+                .unwrap_or_default();
+            (focused_frames, wm_state.get_urgent_frames())
+        };
+        for &frame_win in self.window_frame_map.borrow().values() {
+            let border_pixel = if focused_frames.contains(&frame_win) {
+                FOCUSED_BORDER_PIXEL
+            } else if urgent_frames.contains(&frame_win) {
+                URGENT_BORDER_PIXEL
+            } else {
+                UNFOCUSED_BORDER_PIXEL
+            };
+            self.conn
+                .change_window_attributes(
+                    frame_win,
+                    &ChangeWindowAttributesAux::new().border_pixel(border_pixel),
+                )
+                .unwrap();
+        }
+    }
+
+    /// Record that a window needs attention for `source`, or not anymore. The focused
+    /// window never does: the flag is cleared on the client right away.
+    /// Bars are told whenever the set of workspaces with such windows changes.
+    fn set_urgency(&self, client_win: Window, source: Urgency, urgent: bool) {
+        let focused = self.wm_state.borrow().get_focused_window() == Some(client_win);
+        let changed = {
+            let mut windows = match source {
+                Urgency::Hint => self.urgency_hints.borrow_mut(),
+                Urgency::DemandsAttention => self.demanding_attention.borrow_mut(),
+            };
+            if urgent {
+                windows.insert(client_win)
+            } else {
+                windows.remove(&client_win)
+            }
+        };
+        if urgent && focused {
+            self.clear_urgency_source(client_win, source);
+        } else if changed && source == Urgency::DemandsAttention {
+            // the WM owns _NET_WM_STATE once the window is managed
+            self.write_demands_attention(client_win, urgent);
+        }
+        self.update_urgency(client_win);
+    }
+
+    /// Clear both sources of attention of a window, on the client too.
+    fn clear_urgency(&self, client_win: Window) {
+        for source in [Urgency::Hint, Urgency::DemandsAttention] {
+            self.clear_urgency_source(client_win, source);
+        }
+        self.update_urgency(client_win);
+    }
+
+    /// Forget one source of attention of a window and clear it on the client.
+    fn clear_urgency_source(&self, client_win: Window, source: Urgency) {
+        match source {
+            Urgency::Hint => {
+                if !self.urgency_hints.borrow_mut().remove(&client_win) {
+                    return;
+                }
+                let Some(mut hints) = self.get_wm_hints(client_win) else {
+                    return;
+                };
+                if hints.urgent {
+                    hints.urgent = false;
+                    hints.set(&self.conn, client_win).unwrap();
+                }
+            }
+            Urgency::DemandsAttention => {
+                if self.demanding_attention.borrow_mut().remove(&client_win) {
+                    self.write_demands_attention(client_win, false);
+                }
+            }
+        }
+    }
+
+    /// Add or remove `_NET_WM_STATE_DEMANDS_ATTENTION` in the `_NET_WM_STATE` of a client,
+    /// keeping the other states it lists.
+    fn write_demands_attention(&self, client_win: Window, demands_attention: bool) {
+        let state = self.atoms[&Atom::_NET_WM_STATE];
+        let demands_attention_atom = self.atoms[&Atom::_NET_WM_STATE_DEMANDS_ATTENTION];
+        let mut states: Vec<u32> = self
+            .conn
+            .get_property(false, client_win, state, AtomEnum::ATOM, 0, u32::MAX)
+            .unwrap()
+            .reply()
+            .ok()
+            .and_then(|reply| Some(reply.value32()?.collect()))
+            .unwrap_or_default();
+        states.retain(|&atom| atom != demands_attention_atom);
+        if demands_attention {
+            states.push(demands_attention_atom);
+        }
+        self.conn
+            .change_property32(
+                PropMode::REPLACE,
+                client_win,
+                state,
+                AtomEnum::ATOM,
+                &states,
+            )
+            .unwrap();
+    }
+
+    /// Mark a window as needing attention while either source says so.
+    fn update_urgency(&self, client_win: Window) {
+        let urgent = self.urgency_hints.borrow().contains(&client_win)
+            || self.demanding_attention.borrow().contains(&client_win);
+        let urgent_workspaces = {
+            let mut wm_state = self.wm_state.borrow_mut();
+            let before = wm_state.get_urgent_workspaces();
+            wm_state.set_urgent(client_win, urgent);
+            let after = wm_state.get_urgent_workspaces();
+            (before != after).then_some(after)
+        };
+        if let Some(urgent_workspaces) = urgent_workspaces {
+            self.ipc.broadcast(&IpcEvent::Urgent {
+                workspaces: urgent_workspaces.into_iter().map(|i| i + 1).collect(),
+            });
+        }
+    }
+
+    fn focus_urgent(&self) -> Result<()> {
+        let urgent_window = self.wm_state.borrow().find_urgent_window();
+        if let Some(client_win) = urgent_window {
+            self.focus_window(client_win, true);
+        }
+        Ok(())
+    }
+
+    fn get_wm_hints(&self, window: Window) -> Option<WmHints> {
+        // fails when the client did not set any
+        WmHints::get(&self.conn, window).ok()?.reply().ok()
+    }
+
+    fn handle_property_notify(&self, event: PropertyNotifyEvent) {
+        if !self.window_frame_map.borrow().contains_key(&event.window) {
+            return;
+        }
+        if event.atom == u32::from(AtomEnum::WM_NAME)
+            || event.atom == self.atoms[&Atom::_NET_WM_NAME]
+        {
+            self.apply_title_rules(event.window);
+            return;
+        }
+        if event.atom != u32::from(AtomEnum::WM_HINTS) {
+            return;
+        }
+        let urgent = self
+            .get_wm_hints(event.window)
+            .is_some_and(|hints| hints.urgent);
+        self.set_urgency(event.window, Urgency::Hint, urgent);
+        self.update_frame_borders();
+    }
+
+    fn handle_client_message(&self, event: ClientMessageEvent) {
+        if !self.window_frame_map.borrow().contains_key(&event.window) {
+            return;
+        }
+        let data = event.data.as_data32();
+        if event.type_ == self.atoms[&Atom::_NET_WM_STATE] {
+            let demands_attention = self.atoms[&Atom::_NET_WM_STATE_DEMANDS_ATTENTION];
+            if data[1] != demands_attention && data[2] != demands_attention {
+                return;
+            }
+            let urgent = match data[0] {
+                NET_WM_STATE_REMOVE => false,
+                NET_WM_STATE_ADD => true,
+                NET_WM_STATE_TOGGLE => !self.demanding_attention.borrow().contains(&event.window),
+                _ => return,
+            };
+            self.set_urgency(event.window, Urgency::DemandsAttention, urgent);
+            self.update_frame_borders();
+        } else if event.type_ == self.atoms[&Atom::_NET_ACTIVE_WINDOW] {
+            self.activate_window(event.window, data[0]);
+        }
+    }
+
+    /// Handle a window asking to be activated, according to `focus_on_window_activation`.
+    /// Requests from pagers are user actions and always honored.
+    fn activate_window(&self, client_win: Window, source: u32) {
+        let policy = if source == SOURCE_PAGER {
+            FocusOnWindowActivation::Focus
+        } else {
+            self.config.borrow().get_focus_on_window_activation()
+        };
+        let visible = {
+            let wm_state = self.wm_state.borrow();
+            wm_state
+                .find_workspace(client_win)
+                .is_some_and(|w| wm_state.is_workspace_shown(w))
+        };
+        info!("Window {} asks to be activated", client_win);
+        match policy {
+            FocusOnWindowActivation::Focus => self.focus_window(client_win, true),
+            FocusOnWindowActivation::Smart if visible => self.focus_window(client_win, true),
+            FocusOnWindowActivation::Smart | FocusOnWindowActivation::Urgent => {
+                self.set_urgency(client_win, Urgency::DemandsAttention, true);
+                self.update_frame_borders();
+            }
+            FocusOnWindowActivation::None => {}
+        }
+    }
+
+    /// Give the input focus to a client according to its input model (ICCCM §4.1.7).
+    /// Clients accepting input get `SetInputFocus`, which is the default without `WM_HINTS`.
+    /// Clients taking part in `WM_TAKE_FOCUS` are sent it, and set the focus themselves
+    /// when they do not accept input directly. No-input clients are left alone.
+    fn focus_client(&self, client_win: Window) {
+        let accepts_input = self
+            .get_wm_hints(client_win)
+            .and_then(|hints| hints.input)
+            .unwrap_or(true);
+        if accepts_input {
+            self.set_input_focus(client_win);
+        }
+        if self.supports_protocol(client_win, Atom::WM_TAKE_FOCUS) {
+            let event = ClientMessageEvent::new(
+                32,
+                client_win,
+                self.atoms[&Atom::WM_PROTOCOLS],
+                [
+                    self.atoms[&Atom::WM_TAKE_FOCUS],
+                    *self.last_timestamp.borrow(),
+                    0,
+                    0,
+                    0,
+                ],
+            );
+            self.conn
+                .send_event(false, client_win, EventMask::NO_EVENT, event)
+                .unwrap();
+        }
+    }
+
+    /// Whether a client lists `protocol` in its `WM_PROTOCOLS`.
+    fn supports_protocol(&self, window: Window, protocol: Atom) -> bool {
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                window,
+                self.atoms[&Atom::WM_PROTOCOLS],
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )
+            .unwrap()
+            .reply();
+        let Ok(reply) = reply else {
+            return false;
+        };
+        let Some(mut protocols) = reply.value32() else {
+            return false;
+        };
+        protocols.any(|atom| atom == self.atoms[&protocol])
+    }
+
+    fn set_input_focus(&self, window: Window) {
+        let result = self
+            .conn
+            .set_input_focus(InputFocus::POINTER_ROOT, window, CURRENT_TIME)
+            .unwrap()
+            .check();
+        if let Err(e) = result {
+            warn!("Cannot focus {}: {:?}", window, e);
+        }
+    }
+
+    fn handle_map_request(&self, event: MapRequestEvent) {
+        let client_win = event.window;
+        self.frame(client_win, false);
+        self.adopt_pending_scratchpad(client_win);
+    }
+
+    fn frame(&self, client_win: Window, scanning: bool) {
+        let conn = &self.conn;
+        let screen = &conn.setup().roots[self.screen_num];
+        let client_win_attrs = conn
+            .get_window_attributes(client_win)
+            .unwrap()
+            .reply()
+            .unwrap();
+
+        if scanning {
+            if client_win_attrs.override_redirect
+                || client_win_attrs.map_state != MapState::VIEWABLE
+            {
+                return;
+            }
+        }
+
+        let frame_win: Window = conn.generate_id().unwrap();
+
+        // let config = ConfigureWindowAux::new()
+        //     .width(self.screen().width_in_pixels as u32)
+        //     .height(self.screen().height_in_pixels as u32);
+        // conn.configure_window(client_win, &config)
+        //     .unwrap()
+        //     .check()
+        //     .unwrap();
+        let attrs = CreateWindowAux::new()
+            .background_pixel(screen.black_pixel)
+            .border_pixel(UNFOCUSED_BORDER_PIXEL)
+            .override_redirect(1)
+            .event_mask(EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY);
+
+        let properties = self.get_window_properties(client_win);
+        let rules = self.match_rules(&properties, |_| true);
+        let current_workspace = self.wm_state.borrow().get_current_workspace_index();
+        let workspace = self
+            .spawned_workspace(client_win)
+            .or_else(|| self.rule_workspace(&rules))
+            .unwrap_or(current_workspace);
+
+        let ((width, height), (x, y)) = {
+            let mut wm_state = self.wm_state.borrow_mut();
+            let new_container = wm_state.new_container(client_win, frame_win, workspace);
+            (new_container.get_dimensions(), new_container.get_position())
+        };
+        let (width, height) = inner_size(width, height);
+        conn.create_window(
+            screen.root_depth,
+            frame_win,
+            screen.root,
+            x as i16,
+            y as i16,
+            width as u16,
+            height as u16,
+            FRAME_BORDER_WIDTH as u16,
+            client_win_attrs.class,
+            screen.root_visual,
+            &attrs,
+        )
+        .unwrap();
+        conn.change_save_set(SetMode::INSERT, client_win).unwrap();
+        conn.reparent_window(client_win, frame_win, 0, 0).unwrap();
+        // windows assigned to another workspace stay hidden until it is shown
+        if self.wm_state.borrow().is_workspace_shown(workspace) {
+            let cookie = conn.map_window(frame_win).unwrap();
+            self.ignore_enter_notify(cookie.sequence_number());
+            self.raise_floating_frames();
+        }
+
+        self.grab_buttons(frame_win);
+        let config = ChangeWindowAttributesAux::default().event_mask(
+            EventMask::ENTER_WINDOW | EventMask::FOCUS_CHANGE | EventMask::PROPERTY_CHANGE,
+        );
+        conn.change_window_attributes(client_win, &config).unwrap();
+        conn.map_window(client_win).unwrap();
+        self.window_frame_map
+            .borrow_mut()
+            .insert(client_win, frame_win);
+        if self
+            .get_wm_hints(client_win)
+            .is_some_and(|hints| hints.urgent)
+        {
+            self.urgency_hints.borrow_mut().insert(client_win);
+        }
+        if self.demands_attention(client_win) {
+            self.demanding_attention.borrow_mut().insert(client_win);
+        }
+        self.update_urgency(client_win);
+        self.applied_rules
+            .borrow_mut()
+            .insert(client_win, rules.clone());
+        self.run_rule_actions(client_win, &rules);
+        // the new container is already focused in the WM state
+        self.apply_focus(false);
+    }
+
+    /// Read the properties window rules are matched against.
+    fn get_window_properties(&self, window: Window) -> WindowProperties {
+        let (instance, class) = WmClass::get(&self.conn, window)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|wm_class| {
+                (
+                    String::from_utf8_lossy(wm_class.instance()).into_owned(),
+                    String::from_utf8_lossy(wm_class.class()).into_owned(),
+                )
+            })
+            .unwrap_or_default();
+        let title = self
+            .get_string_property(window, self.atoms[&Atom::_NET_WM_NAME])
+            .or_else(|| self.get_string_property(window, AtomEnum::WM_NAME.into()))
+            .unwrap_or_default();
+        WindowProperties {
+            class,
+            instance,
+            role: self
+                .get_string_property(window, self.atoms[&Atom::WM_WINDOW_ROLE])
+                .unwrap_or_default(),
+            window_type: self.get_window_type(window),
+            title,
+        }
+    }
+
+    fn get_string_property(&self, window: Window, property: u32) -> Option<String> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX)
+            .unwrap()
+            .reply()
+            .ok()?;
+        (reply.format == 8).then(|| String::from_utf8_lossy(&reply.value).into_owned())
+    }
+
+    /// Whether a client set `_NET_WM_STATE_DEMANDS_ATTENTION` before it was mapped.
+    fn demands_attention(&self, window: Window) -> bool {
+        let demands_attention = self.atoms[&Atom::_NET_WM_STATE_DEMANDS_ATTENTION];
+        self.conn
+            .get_property(
+                false,
+                window,
+                self.atoms[&Atom::_NET_WM_STATE],
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )
+            .unwrap()
+            .reply()
+            .ok()
+            .and_then(|reply| Some(reply.value32()?.any(|atom| atom == demands_attention)))
+            .unwrap_or(false)
+    }
+
+    /// The first type of `_NET_WM_WINDOW_TYPE`, such as `dialog`, or `normal` without any.
+    fn get_window_type(&self, window: Window) -> String {
+        let window_type = self
+            .conn
+            .get_property(
+                false,
+                window,
+                self.atoms[&Atom::_NET_WM_WINDOW_TYPE],
+                AtomEnum::ATOM,
+                0,
+                1,
+            )
+            .unwrap()
+            .reply()
+            .ok()
+            .and_then(|reply| reply.value32()?.next());
+        let Some(name) =
+            window_type.and_then(|atom| self.conn.get_atom_name(atom).ok()?.reply().ok())
+        else {
+            return "normal".into();
+        };
+        let name = String::from_utf8_lossy(&name.name);
+        name.trim_start_matches("_NET_WM_WINDOW_TYPE_")
+            .to_lowercase()
+    }
+
+    /// Indices of the rules matching a window, among the ones accepted by `filter`.
+    fn match_rules(
+        &self,
+        properties: &WindowProperties,
+        filter: impl Fn(usize) -> bool,
+    ) -> Vec<usize> {
+        self.config
+            .borrow()
+            .get_rules()
+            .iter()
+            .enumerate()
+            .filter(|&(i, rule)| filter(i) && rule.criteria.matches(properties))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The workspace a window goes to when its program was started for one,
+    /// found by its startup notification id or the process id it advertises.
+    fn spawned_workspace(&self, client_win: Window) -> Option<usize> {
+        let startup_id = self.get_string_property(client_win, self.atoms[&Atom::_NET_STARTUP_ID]);
+        // the spawned shell or launcher may have started the program as a child
+        let pids = self
+            .get_window_pid(client_win)
+            .map(process_ancestors)
+            .unwrap_or_default();
+        let spawn = {
+            let mut pending = self.pending_spawns.borrow_mut();
+            let index = pending.iter().position(|s| {
+                startup_id.as_deref() == Some(s.startup_id.as_str()) || pids.contains(&s.pid)
+            })?;
+            pending.remove(index)
+        };
+        self.update_busy_cursor();
+        Some(spawn.workspace)
+    }
+
+    /// Show a busy cursor on the root window while programs started
+    /// for a workspace did not show their window yet.
+    fn update_busy_cursor(&self) {
+        let cursor = if self.pending_spawns.borrow().is_empty() {
+            *self.root_cursor.borrow()
+        } else {
+            *self.busy_cursor.borrow()
+        };
+        self.conn
+            .change_window_attributes(
+                self.screen().root,
+                &ChangeWindowAttributesAux::default().cursor(cursor),
+            )
+            .unwrap();
+    }
+
+    fn get_window_pid(&self, window: Window) -> Option<u32> {
+        self.conn
+            .get_property(
+                false,
+                window,
+                self.atoms[&Atom::_NET_WM_PID],
+                AtomEnum::CARDINAL,
+                0,
+                1,
+            )
+            .unwrap()
+            .reply()
+            .ok()?
+            .value32()?
+            .next()
+    }
+
+    /// The workspace the first of the rules placing windows assigns to.
+    fn rule_workspace(&self, rules: &[usize]) -> Option<usize> {
+        let config = self.config.borrow();
+        let workspace = rules
+            .iter()
+            .find_map(|&i| config.get_rules()[i].workspace.as_ref())?;
+        parse_workspace("rules", workspace)
+            .map_err(|e| warn!("Invalid rule: {}", e))
+            .ok()
+    }
+
+    /// Run the actions of rules on a window. Commands act on the focused container, so
+    /// the window is made their target, focused on its own workspace without showing it,
+    /// then the previous focus of that workspace is restored.
+    fn run_rule_actions(&self, client_win: Window, rules: &[usize]) {
+        let actions: Vec<String> = rules
+            .iter()
+            .flat_map(|&i| self.config.borrow().get_rules()[i].actions.clone())
+            .collect();
+        if actions.is_empty() {
+            return;
+        }
+        let Some(previous_focus) = self.wm_state.borrow_mut().set_command_target(client_win) else {
+            return;
+        };
+        for action in actions {
+            if let Err(e) = self.run_command(&action) {
+                warn!("Rule action {} failed: {}", action, e);
+            }
+        }
+        self.wm_state
+            .borrow_mut()
+            .clear_command_target(previous_focus);
+    }
+
+    /// Apply the rules matching on the title that did not match the previous title.
+    /// Title rules that stopped matching apply again once the title matches again.
+    fn apply_title_rules(&self, client_win: Window) {
+        let properties = self.get_window_properties(client_win);
+        let is_title_rule = |i: usize| self.config.borrow().get_rules()[i].criteria.has_title();
+        let matching = self.match_rules(&properties, is_title_rule);
+        let rules: Vec<usize> = {
+            let mut applied_rules = self.applied_rules.borrow_mut();
+            let applied = applied_rules.entry(client_win).or_default();
+            let rules = matching
+                .iter()
+                .copied()
+                .filter(|i| !applied.contains(i))
+                .collect();
+            applied.retain(|&i| !is_title_rule(i));
+            applied.extend(&matching);
+            rules
+        };
+        if rules.is_empty() {
+            return;
+        }
+
+        if let Some(workspace) = self.rule_workspace(&rules) {
+            let frames = self.wm_state.borrow_mut().move_window_to_workspace(
+                client_win,
+                workspace,
+                &self.focus_history.borrow(),
+            );
+            self.show_moved_frames(&frames, workspace);
+        }
+        self.run_rule_actions(client_win, &rules);
+        self.apply_focus(false);
+    }
+
+    /// Move the focused container to another workspace.
+    fn move_to_workspace(&self, index: usize) -> Result<()> {
+        let frames = self
+            .wm_state
+            .borrow_mut()
+            .move_focused_container_to_workspace(index, &self.focus_history.borrow());
+        self.show_moved_frames(&frames, index);
+        self.apply_focus(false);
+        Ok(())
+    }
+
+    /// Hide the focused container in the scratchpad.
+    fn move_to_scratchpad(&self) -> Result<()> {
+        let frames = self
+            .wm_state
+            .borrow_mut()
+            .move_focused_to_scratchpad(&self.focus_history.borrow());
+        for frame_win in frames {
+            self.conn.unmap_window(frame_win).unwrap();
+        }
+        self.apply_focus(false);
+        Ok(())
+    }
+
+    /// Toggle the scratchpad windows matching `criteria`.
+    /// A matching window shown on the current workspace is focused if it is not yet,
+    /// otherwise it is hidden and the next one is shown, until all were cycled through.
+    /// When none is shown, the first one is brought to the current workspace.
+    fn show_scratchpad(&self, criteria: &WindowCriteria) -> Result<()> {
+        let (candidates, current_workspace, focused_window) = {
+            let wm_state = self.wm_state.borrow();
+            (
+                wm_state.get_scratchpad_windows(),
+                wm_state.get_current_workspace_index(),
+                wm_state.get_focused_window(),
+            )
+        };
+        let candidates: Vec<Window> = candidates
+            .into_iter()
+            .filter(|&w| criteria.matches(&self.get_window_properties(w)))
+            .collect();
+        let shown = candidates
+            .iter()
+            .position(|&w| self.wm_state.borrow().find_workspace(w) == Some(current_workspace));
+        let next = match shown {
+            Some(index) if focused_window == Some(candidates[index]) => {
+                let frames = self
+                    .wm_state
+                    .borrow_mut()
+                    .hide_scratchpad_window(candidates[index], &self.focus_history.borrow());
+                for frame_win in frames {
+                    self.conn.unmap_window(frame_win).unwrap();
+                }
+                candidates.get(index + 1).copied()
+            }
+            Some(index) => {
+                self.focus_window(candidates[index], true);
+                return Ok(());
+            }
+            None => candidates.first().copied(),
+        };
+        if let Some(client_win) = next {
+            let frames = self
+                .wm_state
+                .borrow_mut()
+                .show_scratchpad_window(client_win, &self.focus_history.borrow());
+            self.show_moved_frames(&frames, current_workspace);
+            self.raise_floating_frames();
+        }
+        self.apply_focus(false);
+        Ok(())
+    }
+
+    /// Toggle a scratchpad configured by name, spawning its command when
+    /// no window matches it yet.
+    fn toggle_named_scratchpad(&self, name: &str) -> Result<()> {
+        let config = self.config.borrow();
+        let scratchpad = config
+            .get_scratchpad(name)
+            .ok_or_else(|| Error::InvalidArguments("scratchpad".into(), name.into()))?;
+        let managed: Vec<Window> = self.window_frame_map.borrow().keys().copied().collect();
+        let existing = managed
+            .into_iter()
+            .find(|&w| scratchpad.criteria.matches(&self.get_window_properties(w)));
+        match existing {
+            Some(client_win) => {
+                if !self
+                    .wm_state
+                    .borrow()
+                    .get_scratchpad_windows()
+                    .contains(&client_win)
+                {
+                    self.send_to_scratchpad(client_win, scratchpad.size);
+                }
+                self.show_scratchpad(&scratchpad.criteria)
+            }
+            None => {
+                let mut pending_scratchpads = self.pending_scratchpads.borrow_mut();
+                // the window of a previous toggle may still be on its way
+                if !pending_scratchpads.iter().any(|s| s.name == name) {
+                    let pid = self.spawn_child(&scratchpad.command, &self.next_startup_id())?;
+                    pending_scratchpads.push(PendingScratchpad {
+                        name: name.into(),
+                        pid,
+                        deadline: Instant::now() + SPAWN_TIMEOUT,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Turn a newly mapped window into the named scratchpad waiting for it, and show it.
+    fn adopt_pending_scratchpad(&self, client_win: Window) {
+        if self.pending_scratchpads.borrow().is_empty()
+            || !self.window_frame_map.borrow().contains_key(&client_win)
+        {
+            return;
+        }
+        let properties = self.get_window_properties(client_win);
+        let position = self
+            .pending_scratchpads
+            .borrow()
+            .iter()
+            .position(|pending| {
+                self.config
+                    .borrow()
+                    .get_scratchpad(&pending.name)
+                    .is_some_and(|s| s.criteria.matches(&properties))
+            });
+        let Some(position) = position else {
+            return;
+        };
+        let name = self.pending_scratchpads.borrow_mut().remove(position).name;
+        let config = self.config.borrow();
+        let Some(scratchpad) = config.get_scratchpad(&name) else {
+            return;
+        };
+        self.send_to_scratchpad(client_win, scratchpad.size);
+        if let Err(e) = self.show_scratchpad(&scratchpad.criteria) {
+            warn!("Cannot show scratchpad {}: {}", name, e);
+        }
+    }
+
+    fn send_to_scratchpad(&self, client_win: Window, size: Option<(u32, u32)>) {
+        let frames = self.wm_state.borrow_mut().move_window_to_scratchpad(
+            client_win,
+            size,
+            &self.focus_history.borrow(),
+        );
+        for frame_win in frames {
+            self.conn.unmap_window(frame_win).unwrap();
+        }
+    }
+
+    /// Keep the floating windows of the shown workspaces above the tiled ones.
+    fn raise_floating_frames(&self) {
+        let config = ConfigureWindowAux::new().stack_mode(StackMode::ABOVE);
+        for frame_win in self.wm_state.borrow().get_floating_frames() {
+            self.conn.configure_window(frame_win, &config).unwrap();
+        }
+    }
+
+    /// Map or unmap the frames moved to `workspace`, depending on whether it is shown.
+    fn show_moved_frames(&self, frames: &[Window], workspace: usize) {
+        let shown = self.wm_state.borrow().is_workspace_shown(workspace);
+        for &frame_win in frames {
+            if shown {
+                let cookie = self.conn.map_window(frame_win).unwrap();
+                self.ignore_enter_notify(cookie.sequence_number());
+            } else {
+                self.conn.unmap_window(frame_win).unwrap();
+            }
+        }
+    }
+
+    fn handle_configure_request(&self, event: ConfigureRequestEvent) {
+        let conn = &self.conn;
+        let configure_attrs = ConfigureWindowAux::from_configure_request(&event);
+
+        if let Some(client) = self.clients.borrow().get(&event.window) {
+            conn.configure_window(client.frame_win, &configure_attrs)
+                .unwrap();
+            // the frame no longer has the geometry of its container
+            self.frame_geometries.borrow_mut().remove(&client.frame_win);
+        }
+
+        conn.configure_window(event.window, &configure_attrs)
+            .unwrap();
+    }
+
+    /// Grab the mouse bindings that apply to a frame and its client.
+    /// Bindings on the root window need no grab, its button events are selected in `init`.
+    fn grab_buttons(&self, frame_win: Window) {
+        // Every click is grabbed synchronously to focus the window, then replayed
+        // to the client. The grabs of the bindings below override it.
+        self.conn
+            .grab_button(
+                false,
+                frame_win,
+                EventMask::BUTTON_PRESS,
+                GrabMode::SYNC,
+                GrabMode::ASYNC,
+                NONE,
+                NONE,
+                ButtonIndex::ANY,
+                ModMask::ANY,
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let config = self.config.borrow();
+        let Some(mouse_bindings) = config.get_mouse_bindings() else {
+            return;
+        };
+        for binding in mouse_bindings.keys() {
+            if binding.context == ButtonContext::Root {
+                continue;
+            }
+            for lock_mask in lock_mask_combinations(*self.lock_masks.borrow()) {
+                self.conn
+                    .grab_button(
+                        false,
+                        frame_win,
+                        EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE,
                         GrabMode::ASYNC,
                         GrabMode::ASYNC,
+                        NONE,
+                        NONE,
+                        ButtonIndex::from(binding.button),
+                        ModMask::from(binding.modifier_mask as u16 | lock_mask),
                     )
                     .unwrap()
                     .check()
                     .unwrap();
+            }
+        }
+    }
+
+    /// Grab the bindings of `mode` on the root window, replacing any previous grab.
+    fn grab_keys(&self, mode: &str) {
+        let root = self.screen().root;
+        self.conn
+            .ungrab_key(Grab::ANY, root, ModMask::ANY)
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let keyboard = self.keyboard.borrow();
+        let config = self.config.borrow();
+        if let Some(escape_key) = config.get_escape_key(mode) {
+            for (keycode, level_mask) in keyboard.keycodes_for(escape_key.keysym) {
+                let modifiers = escape_key.modifier_mask as u16 | level_mask;
+                if let Err(e) = self.grab_key(root, keycode as u8, modifiers) {
+                    warn!(
+                        "Cannot grab {}, another client may hold it: {}",
+                        escape_key, e
+                    );
+                }
+            }
+        }
+
+        let Some(config_key_map) = config.get_key_maps(mode) else {
+            return;
+        };
+        // only the first key of a sequence is grabbed, the rest go through a keyboard grab
+        for key in config_key_map.keys() {
+            for (keycode, level_mask) in keyboard.keycodes_for(key.keysym) {
+                let mod_mask = key.modifier_mask | config.get_mode_mask(mode);
+                if let Err(e) = self.grab_key(root, keycode as u8, mod_mask as u16 | level_mask) {
+                    warn!("Cannot grab {}, another client may hold it: {}", key, e);
                 }
             }
         }
     }
 
+    /// Grab a key for every combination of the lock modifiers,
+    /// so bindings keep working while CapsLock, NumLock or ScrollLock is on.
+    /// Fails with BadAccess when another client grabbed the same combination.
+    fn grab_key(&self, window: Window, keycode: u8, modifiers: u16) -> Result<()> {
+        for lock_mask in lock_mask_combinations(*self.lock_masks.borrow()) {
+            self.conn
+                .grab_key(
+                    false,
+                    window,
+                    ModMask::from(modifiers | lock_mask),
+                    keycode,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )?
+                .check()?;
+        }
+        Ok(())
+    }
+
+    /// Strip CapsLock, NumLock and ScrollLock from a key or button state.
+    fn clean_mask(&self, state: u32) -> u32 {
+        let (num_lock, scroll_lock) = *self.lock_masks.borrow();
+        let locks = u16::from(ModMask::LOCK) | num_lock | scroll_lock;
+        state & !(locks as u32)
+    }
+
+    /// Look up which real modifiers NumLock and ScrollLock are bound to.
+    /// They are usually Mod2 and Mod5 (or nothing), but the mapping is up to the user.
+    fn update_lock_masks(&self) {
+        let modifier_mapping = self.conn.get_modifier_mapping().unwrap().reply().unwrap();
+        let keycodes_per_modifier = modifier_mapping.keycodes_per_modifier() as usize;
+        let keyboard = self.keyboard.borrow();
+
+        let modifier_of = |keysym: Keysym| -> u16 {
+            let keycodes = keyboard.keycodes_for(keysym);
+            modifier_mapping
+                .keycodes
+                .iter()
+                .position(|&k| k != 0 && keycodes.iter().any(|&(c, _)| c == k as Keycode))
+                .map(|i| 1 << (i / keycodes_per_modifier))
+                .unwrap_or(0)
+        };
+
+        *self.lock_masks.borrow_mut() = (
+            modifier_of(xkbcommon::xkb::KEY_Num_Lock),
+            modifier_of(xkbcommon::xkb::KEY_Scroll_Lock),
+        );
+    }
+
+    /// Rebuild everything derived from the keyboard mapping.
+    fn reload_keyboard(&self) {
+        info!("Keyboard mapping changed, regrabbing keys");
+        self.keyboard.borrow_mut().reload();
+        self.update_lock_masks();
+        self.grab_keys(&self.wm_mode.borrow());
+    }
+
+    /// Ask for the XKB events needed to keep the keyboard state and keymap up to date.
+    /// The extension was enabled for the connection by `new`.
+    fn select_xkb_events(&self) {
+        let events = xkb::EventType::NEW_KEYBOARD_NOTIFY
+            | xkb::EventType::MAP_NOTIFY
+            | xkb::EventType::STATE_NOTIFY;
+        self.conn
+            .xkb_select_events(
+                xkb::ID::USE_CORE_KBD.into(),
+                0u16.into(),
+                events,
+                0u16.into(),
+                0u16.into(),
+                &xkb::SelectEventsAux::new(),
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+        // without detectable auto repeat, a held key sends a release before every repeat
+        let flags = self
+            .conn
+            .xkb_per_client_flags(
+                xkb::ID::USE_CORE_KBD.into(),
+                xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT,
+                xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT,
+                0u32.into(),
+                0u32.into(),
+                0u32.into(),
+            )
+            .map_err(Error::from)
+            .and_then(|cookie| cookie.reply().map_err(Error::from));
+        let detectable = flags.is_ok_and(|flags| {
+            u32::from(flags.value) & u32::from(xkb::PerClientFlag::DETECTABLE_AUTO_REPEAT) != 0
+        });
+        if !detectable {
+            warn!("No detectable auto repeat, release bindings run on every repeat");
+        }
+    }
+
+    /// Ask to be notified when outputs are connected or disconnected.
+    fn select_randr_events(&self) {
+        if !self.has_randr {
+            warn!("RandR 1.3 is not available, output conditions never hold");
+            return;
+        }
+        self.conn
+            .randr_select_input(
+                self.screen().root,
+                randr::NotifyMask::SCREEN_CHANGE | randr::NotifyMask::OUTPUT_CHANGE,
+            )
+            .unwrap()
+            .check()
+            .unwrap();
+    }
+
+    /// Lay the workspaces out over the outputs again, and load the config again when the
+    /// connected outputs changed and it depends on them.
+    fn handle_output_change(&self) {
+        self.update_output_areas();
+        let outputs = match connected_outputs(&self.conn, self.screen().root) {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                warn!("Cannot list the outputs: {}", e);
+                return;
+            }
+        };
+        if *self.outputs.borrow() == outputs {
+            return;
+        }
+        info!("Connected outputs: {}", outputs.join(", "));
+        *self.outputs.borrow_mut() = outputs;
+        if self.config.borrow().depends_on_outputs() {
+            // failures are logged and broadcasted
+            let _ = self.reload_config();
+        }
+    }
+
+    /// Show a workspace on each output, in its area of the screen.
+    fn update_output_areas(&self) {
+        let areas = match output_areas(&self.conn, self.screen().root) {
+            Ok(areas) => areas,
+            Err(e) => {
+                warn!("Cannot read the output areas: {}", e);
+                return;
+            }
+        };
+        let (hidden, shown) = self.wm_state.borrow_mut().set_outputs(&areas);
+        for frame_win in hidden {
+            self.conn.unmap_window(frame_win).unwrap();
+        }
+        self.map_frames(&shown);
+        self.raise_floating_frames();
+        self.apply_focus(false);
+    }
+
+    fn handle_mapping_notify(&self, event: MappingNotifyEvent) {
+        if event.request == Mapping::POINTER {
+            return;
+        }
+        self.reload_keyboard();
+    }
+
+    /// Parse the config file again. It replaces the current config once the
+    /// running command returned, the current one is kept if it does not parse.
+    fn reload_config(&self) -> Result<()> {
+        let context = ConfigContext::new(self.outputs.borrow().clone());
+        match config::load_config(self.config_path.as_deref(), &context) {
+            Ok(config) => {
+                info!("Config parsed, reloading");
+                self.warn_config_problems(&config);
+                *self.pending_config.borrow_mut() = Some(config);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Keeping the current config: {}", e);
+                self.ipc.broadcast(&IpcEvent::Reload {
+                    success: false,
+                    error: Some(e.to_string()),
+                });
+                Err(Error::Config(e.to_string()))
+            }
+        }
+    }
+
+    /// Log what `--check-config` would report. Such problems do not prevent using
+    /// the config, at startup or on reload: the bindings concerned just fail when used.
+    fn warn_config_problems(&self, config: &Config) {
+        for diagnostic in config.check(&Self::command_names(config)) {
+            warn!("{}", diagnostic);
+        }
+    }
+
+    /// Files the config is read from, includes too. With the built-in config,
+    /// the first search path is watched for a config file to appear.
+    fn config_files(&self) -> Vec<String> {
+        let files = self.config.borrow().get_files().to_vec();
+        if !files.is_empty() {
+            return files;
+        }
+        config::get_config_search_paths()
+            .into_iter()
+            .take(1)
+            .collect()
+    }
+
+    /// Start or stop watching the config files, following `auto_reload`.
+    fn update_config_watcher(&self) {
+        let watcher = if self.config.borrow().get_auto_reload() {
+            ConfigWatcher::new(&self.config_files())
+                .map_err(|e| warn!("Cannot watch the config files: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        *self.config_watcher.borrow_mut() = watcher;
+    }
+
+    /// Swap in a reloaded config and refresh everything derived from it:
+    /// commands, key and button grabs, rules and frame borders.
+    fn apply_pending_config(&self) {
+        let Some(config) = self.pending_config.borrow_mut().take() else {
+            return;
+        };
+        *self.commands.borrow_mut() = Self::build_command_map(config.get_custom_commands());
+        *self.config.borrow_mut() = config;
+
+        if self.pending_chord.borrow().is_some() {
+            self.end_chord();
+        }
+        let mode = self.wm_mode.borrow().clone();
+        if self.config.borrow().has_mode(&mode) {
+            self.grab_keys(&mode);
+            self.reset_mode_deadline();
+        } else {
+            let _ = self.set_mode(DEFAULT_MODE);
+        }
+        let frames: Vec<Window> = self.window_frame_map.borrow().values().copied().collect();
+        for frame_win in frames {
+            self.grab_buttons(frame_win);
+        }
+        // rules already applied are not run again when a title changes
+        let clients: Vec<Window> = self.window_frame_map.borrow().keys().copied().collect();
+        for client_win in clients {
+            let rules = self.match_rules(&self.get_window_properties(client_win), |_| true);
+            self.applied_rules.borrow_mut().insert(client_win, rules);
+        }
+        self.update_frame_borders();
+        self.update_config_watcher();
+        let autostart_always = self.config.borrow().get_autostart_always().to_vec();
+        self.start_programs(&autostart_always);
+        info!("Config reloaded");
+        self.ipc.broadcast(&IpcEvent::Reload {
+            success: true,
+            error: None,
+        });
+    }
+
+    /// Switch the active binding mode, replacing the key grabs on the root window.
+    fn set_mode(&self, mode: &str) -> Result<()> {
+        if !self.config.borrow().has_mode(mode) {
+            return Err(Error::UnknownMode(mode.into()));
+        }
+
+        self.grab_keys(mode);
+        *self.wm_mode.borrow_mut() = mode.into();
+        self.reset_mode_deadline();
+        info!("Switched to mode {}", mode);
+        self.ipc.broadcast(&IpcEvent::Mode { name: mode.into() });
+        Ok(())
+    }
+
+    fn reset_mode_deadline(&self) {
+        let timeout = self
+            .config
+            .borrow()
+            .get_mode_timeout(&self.wm_mode.borrow());
+        *self.mode_deadline.borrow_mut() = timeout.map(|t| Instant::now() + t);
+    }
+
     fn handle_unmap_notify(&self, event: UnmapNotifyEvent) {
         let conn = &self.conn;
         let screen = self.screen();
 
         let mut window_frame_map = self.window_frame_map.borrow_mut();
-        if let Some(_) = window_frame_map.get(&event.window) {
+        let frame_win = if let Some(&frame_win) = window_frame_map.get(&event.window) {
             conn.change_save_set(SetMode::DELETE, event.window).unwrap();
             conn.reparent_window(event.window, screen.root, 0, 0)
                 .unwrap();
             window_frame_map.remove(&event.window);
+            frame_win
         } else {
             // remove the frame, no need to continue processing
             return;
-        }
+        };
 
+        self.focus_history
+            .borrow_mut()
+            .retain(|&w| w != event.window);
+        self.applied_rules.borrow_mut().remove(&event.window);
+        self.urgency_hints.borrow_mut().remove(&event.window);
+        self.demanding_attention.borrow_mut().remove(&event.window);
         let mut wm_state = self.wm_state.borrow_mut();
-        wm_state.remove_container(event.window);
+        // a window hidden in the scratchpad is on no workspace
+        if wm_state.remove_from_scratchpad(event.window) {
+            conn.destroy_window(frame_win).unwrap();
+            self.frame_geometries.borrow_mut().remove(&frame_win);
+            return;
+        }
+        wm_state.remove_container(event.window, &self.focus_history.borrow());
         let removed_containers = wm_state.get_removed_containers();
         // println!(
         //     "removed containers: {:?}",
@@ -476,125 +2339,463 @@ impl WM {
         for c in removed_containers {
             let Some(frame_win_id ) = c.frame_win_id else { continue;};
             conn.destroy_window(frame_win_id).unwrap();
+            self.frame_geometries.borrow_mut().remove(&frame_win_id);
         }
         wm_state.clean_removed_containers();
-        let Some(focusing_container) = wm_state.get_focusing_container() else {
-            return
-        };
-        let Some(focusing_window_id) = focusing_container.main_win_id else {return};
-        self.conn
-            .set_input_focus(InputFocus::POINTER_ROOT, focusing_window_id, CURRENT_TIME)
-            .unwrap()
-            .check()
-            .unwrap();
+        drop(wm_state);
+        self.apply_focus(false);
     }
 
     fn handle_key_press(&self, event: KeyPressEvent) {
-        let conn = &self.conn;
-        let setup = conn.setup();
-        let keymap = conn
-            .get_keyboard_mapping(setup.min_keycode, setup.max_keycode - setup.min_keycode + 1)
-            .unwrap()
-            .reply()
-            .unwrap();
-        let keysyms_per_keycode = keymap.keysyms_per_keycode as usize;
-        let keycode = event.detail as usize;
-        let state = event.state;
-
-        //(K - first_code) * keysyms_per_code_return + N
-        let keysym_index = (keycode - (setup.min_keycode as usize)) * keysyms_per_keycode;
-        let key_sym = keymap.keysyms[keysym_index];
-        let key_map = self.config.get_key_maps("default").unwrap();
-        let state: u32 = state.into();
-        if state != 0 {
-            let state = state & (!self.config.get_mod_mask());
-            if let Some(mod_map) = key_map.get(&key_sym) {
-                if let Some(handler_name) = mod_map.get(&state) {
-                    if let Some(handler) = self.commands.get(handler_name) {
-                        handler(self).unwrap();
-                    }
+        let mode = self.wm_mode.borrow().clone();
+        let keycode = event.detail as Keycode;
+        if self.release_commands.borrow().contains_key(&keycode) {
+            // auto repeat of a key waiting for its release
+            return;
+        }
+        let state = self.clean_mask(event.state.into());
+        // Try the keysym of the current level first (`plus` for Shift+equal),
+        // then the one of the first level with every modifier (`shift+h`).
+        let candidates = {
+            let keyboard = self.keyboard.borrow();
+            let (keysym, unconsumed_state) = keyboard.resolve(keycode, state);
+            [
+                (keysym, unconsumed_state),
+                (keyboard.base_keysym(keycode), state),
+            ]
+        };
+
+        if self.pending_chord.borrow().is_some() {
+            self.continue_chord(&candidates);
+            return;
+        }
+
+        if let Some(escape_key) = self.config.borrow().get_escape_key(&mode) {
+            if candidates.iter().any(|&(keysym, state)| {
+                escape_key.keysym == keysym && escape_key.modifier_mask == state
+            }) {
+                if let Err(e) = self.set_mode(DEFAULT_MODE) {
+                    warn!("Cannot leave mode {}: {}", mode, e);
                 }
+                return;
             }
-        } else {
-            if key_sym == xkbcommon::xkb::KEY_Escape {
-                exit(0);
+        }
+        self.reset_mode_deadline();
+
+        let config = self.config.borrow();
+        let Some(key_map) = config.get_key_maps(&mode) else {
+            return;
+        };
+        // the root window gets every key press while it has the focus
+        let mode_mask = config.get_mode_mask(&mode);
+        let binding = candidates.iter().find_map(|&(keysym, state)| {
+            if state & mode_mask != mode_mask {
+                return None;
             }
+            let key = KeyCompound {
+                modifier_mask: state & !mode_mask,
+                keysym,
+            };
+            key_map.get(&key).map(|b| (key, b))
+        });
+        match binding {
+            Some((_, KeyBinding::Command(command))) => self.run_key_command(keycode, command),
+            Some((key, KeyBinding::Prefix(next))) => self.start_chord(key, next.clone()),
+            None => {}
         }
     }
 
-    fn focus_top(&self) {
-        let stack = self.display_stack.borrow();
-        let len = stack.len();
-        if len > 0 {
-            let top_of_stack = stack[len - 1];
-            self.focus(top_of_stack);
+    /// Run the command of a key binding, or keep it until the key is released.
+    fn run_key_command(&self, keycode: Keycode, command: &KeyCommand) {
+        if command.release {
+            self.release_commands
+                .borrow_mut()
+                .insert(keycode, command.command.clone());
+            return;
+        }
+        if let Err(e) = self.run_command(&command.command) {
+            warn!("Command {} failed: {}", command.command, e);
         }
     }
 
-    fn focus_left(&self) {
-        {
-            let mut stack = self.display_stack.borrow_mut();
-            if let Some(top_of_stack) = stack.pop_back() {
-                stack.push_front(top_of_stack);
+    fn handle_key_release(&self, event: KeyReleaseEvent) {
+        if self.focus_cycle.borrow().is_some() {
+            // the state of the event still has the modifier being released
+            let keycode = event.detail as Keycode;
+            let remaining =
+                self.clean_mask(u32::from(event.state) & 0xff) & !self.modifier_mask_of(keycode);
+            let keysym = self.keyboard.borrow().base_keysym(keycode);
+            if keyboard::is_modifier(keysym) && remaining == 0 {
+                self.end_focus_cycle();
             }
         }
-        self.focus_top();
-    }
-    fn focus_right(&self) {
-        {
-            let mut stack = self.display_stack.borrow_mut();
-            if let Some(top_of_stack) = stack.pop_front() {
-                stack.push_back(top_of_stack);
+        let command = self
+            .release_commands
+            .borrow_mut()
+            .remove(&(event.detail as Keycode));
+        if let Some(command) = command {
+            if let Err(e) = self.run_command(&command) {
+                warn!("Command {} failed: {}", command, e);
             }
         }
-        self.focus_top();
     }
 
-    fn focus(&self, window: Window) {
-        let clients = self.clients.borrow();
-        let client = clients.get(&window).unwrap();
+    /// Wait for the next key of a sequence.
+    /// The keyboard is grabbed so that the key does not reach the focused client.
+    fn start_chord(&self, key: KeyCompound, key_map: KeyMap) {
+        if !self.grab_keyboard() {
+            warn!("Cannot start the key sequence {}", key);
+            return;
+        }
+
+        let keys = vec![key.to_string()];
+        self.ipc.broadcast(&IpcEvent::Chord { keys: keys.clone() });
+        *self.pending_chord.borrow_mut() = Some(PendingChord {
+            keys,
+            key_map,
+            deadline: Instant::now() + self.config.borrow().get_chord_timeout(),
+        });
+    }
 
-        self.conn
-            .circulate_window(Circulate::RAISE_LOWEST, client.frame_win)
+    /// Have every key event sent to the WM rather than to the focused client.
+    fn grab_keyboard(&self) -> bool {
+        let status = self
+            .conn
+            .grab_keyboard(
+                false,
+                self.screen().root,
+                CURRENT_TIME,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+            )
             .unwrap()
-            .check()
-            .unwrap();
-        let config = ConfigureWindowAux::new().stack_mode(StackMode::ABOVE);
-        self.conn
-            .configure_window(client.frame_win, &config)
+            .reply()
             .unwrap()
-            .check()
-            .unwrap();
+            .status;
+        if status != GrabStatus::SUCCESS {
+            warn!("Cannot grab the keyboard: {:?}", status);
+        }
+        status == GrabStatus::SUCCESS
+    }
+
+    /// Keys following a prefix are matched literally, without the mod key.
+    fn continue_chord(&self, candidates: &[(Keysym, u32)]) {
+        if candidates
+            .iter()
+            .all(|&(keysym, _)| keyboard::is_modifier(keysym))
+        {
+            // still waiting for the actual key
+            return;
+        }
+
+        let binding = {
+            let pending = self.pending_chord.borrow();
+            let pending = pending.as_ref().unwrap();
+            candidates.iter().find_map(|&(keysym, state)| {
+                let key = KeyCompound {
+                    modifier_mask: state,
+                    keysym,
+                };
+                pending.key_map.get(&key).map(|b| (key, b.clone()))
+            })
+        };
+        match binding {
+            // Release bindings run on press too: the release would reach the client
+            // once the grab of the sequence ended, and tools that grab the keyboard
+            // themselves only need that grab to be gone.
+            Some((_, KeyBinding::Command(command))) => {
+                self.end_chord();
+                if let Err(e) = self.run_command(&command.command) {
+                    warn!("Command {} failed: {}", command.command, e);
+                }
+            }
+            Some((key, KeyBinding::Prefix(next))) => {
+                let mut pending = self.pending_chord.borrow_mut();
+                let pending = pending.as_mut().unwrap();
+                pending.keys.push(key.to_string());
+                pending.key_map = next;
+                pending.deadline = Instant::now() + self.config.borrow().get_chord_timeout();
+                self.ipc.broadcast(&IpcEvent::Chord {
+                    keys: pending.keys.clone(),
+                });
+            }
+            None => {
+                info!("Key sequence aborted");
+                self.end_chord();
+            }
+        }
+    }
+
+    fn end_chord(&self) {
+        self.pending_chord.borrow_mut().take();
+        // a release after the keyboard grab ends goes to the focused client
+        self.release_commands.borrow_mut().clear();
+        self.conn.ungrab_keyboard(CURRENT_TIME).unwrap();
+        self.ipc.broadcast(&IpcEvent::Chord { keys: Vec::new() });
+    }
+
+    fn focus_in_direction(&self, direction: Direction) -> Result<()> {
+        let target = self
+            .wm_state
+            .borrow()
+            .find_window_in_direction(direction, self.config.borrow().get_focus_wrapping());
+        if let Some(client_win) = target {
+            self.focus_window(client_win, true);
+        }
+        Ok(())
+    }
+
+    fn focus_parent(&self) -> Result<()> {
+        self.wm_state.borrow_mut().focus_parent();
+        self.apply_focus(false);
+        Ok(())
+    }
+
+    fn focus_child(&self) -> Result<()> {
+        self.wm_state.borrow_mut().focus_child();
+        self.apply_focus(false);
+        Ok(())
+    }
+
+    fn move_in_direction(&self, direction: Direction) -> Result<()> {
+        self.wm_state
+            .borrow_mut()
+            .move_focused_container(direction, &self.focus_history.borrow());
+        self.apply_focus(true);
+        Ok(())
     }
 
     fn handle_button_press(&self, event: ButtonPressEvent) {
         info!("ButtonClicked on {}", event.event);
-        self.conn
-            .set_input_focus(InputFocus::POINTER_ROOT, event.event, CURRENT_TIME)
-            .unwrap()
-            .check()
-            .unwrap();
+        let client_win = self
+            .window_frame_map
+            .borrow()
+            .iter()
+            .find(|(_, &frame_win)| frame_win == event.event)
+            .map(|(&client_win, _)| client_win);
+        let context = match client_win {
+            Some(client_win) if event.child == client_win => ButtonContext::Window,
+            Some(_) => ButtonContext::Border,
+            None if event.event == self.screen().root && event.child == NONE => ButtonContext::Root,
+            None => return,
+        };
+        // the button masks of the state are the buttons that were already held
+        let state = self.clean_mask(u32::from(event.state) & 0xff);
+        let binding = ButtonCompound {
+            modifier_mask: state,
+            button: event.detail,
+            context,
+        };
+        // clicking focuses with every policy, and commands act on the clicked window
+        if let Some(client_win) = client_win {
+            self.focus_window(client_win, false);
+        }
+        let Some(command) = self
+            .config
+            .borrow()
+            .get_mouse_bindings()
+            .and_then(|m| m.get(&binding))
+            .cloned()
+        else {
+            // let the client have the click that was frozen by the focus grab
+            self.conn
+                .allow_events(Allow::REPLAY_POINTER, event.time)
+                .unwrap();
+            return;
+        };
+
+        *self.button_press.borrow_mut() = Some((event.root_x, event.root_y, event.time));
+        let result = self.run_command(&command);
+        self.button_press.borrow_mut().take();
+        if let Err(e) = result {
+            warn!("Command {} failed: {}", command, e);
+        }
     }
 
-    fn spawn<S: Into<String>>(cmd: S) {
-        let s = cmd.into();
-        let parts: Vec<&str> = s.split_whitespace().collect();
-        let result = if parts.len() > 1 {
-            Command::new(parts[0])
-                .args(&parts[1..])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-        } else {
-            Command::new(parts[0])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
+    fn handle_button_release(&self, event: ButtonReleaseEvent) {
+        if self.drag.borrow_mut().take().is_some() {
+            self.conn.ungrab_pointer(event.time).unwrap();
+        }
+    }
+
+    /// Move or resize the focused window with the pointer until the button of the
+    /// mouse binding is released. A tiled window starts floating where it was.
+    fn start_drag(&self, kind: DragKind) -> Result<()> {
+        let Some((root_x, root_y, time)) = *self.button_press.borrow() else {
+            let command = if kind == DragKind::Move {
+                "move"
+            } else {
+                "resize"
+            };
+            return Err(Error::InvalidArguments(
+                command.into(),
+                "dragging needs a mouse binding".into(),
+            ));
+        };
+        let geometry = {
+            let mut wm_state = self.wm_state.borrow_mut();
+            wm_state.set_floating(true, &self.focus_history.borrow());
+            wm_state.get_floating_geometry()
+        };
+        let Some(geometry) = geometry else {
+            return Ok(());
+        };
+        self.raise_floating_frames();
+
+        let status = self
+            .conn
+            .grab_pointer(
+                false,
+                self.screen().root,
+                EventMask::POINTER_MOTION | EventMask::BUTTON_RELEASE,
+                GrabMode::ASYNC,
+                GrabMode::ASYNC,
+                NONE,
+                *self.drag_cursor.borrow(),
+                time,
+            )?
+            .reply()?
+            .status;
+        if status != GrabStatus::SUCCESS {
+            warn!("Cannot grab the pointer: {:?}", status);
+            return Ok(());
+        }
+        *self.drag.borrow_mut() = Some(Drag {
+            kind,
+            pointer: (root_x, root_y),
+            geometry,
+        });
+        Ok(())
+    }
+
+    fn handle_motion_notify(&self, event: MotionNotifyEvent) {
+        let drag = self.drag.borrow();
+        let Some(drag) = drag.as_ref() else {
+            return;
         };
+        let dx = i64::from(event.root_x) - i64::from(drag.pointer.0);
+        let dy = i64::from(event.root_y) - i64::from(drag.pointer.1);
+        let (x, y, width, height) = drag.geometry;
+        let mut wm_state = self.wm_state.borrow_mut();
+        match drag.kind {
+            DragKind::Move => wm_state.move_floating(
+                (i64::from(x) + dx).max(0) as u32,
+                (i64::from(y) + dy).max(0) as u32,
+            ),
+            DragKind::Resize => wm_state.resize_floating(
+                (i64::from(width) + dx).max(i64::from(MIN_DRAG_SIZE)) as u32,
+                (i64::from(height) + dy).max(i64::from(MIN_DRAG_SIZE)) as u32,
+            ),
+        }
+    }
+
+    /// Run `exec [--on-workspace] <command>`, given the arguments as written.
+    fn exec(&self, arguments: &str) -> Result<()> {
+        let (command, workspace) = match arguments.strip_prefix("--on-workspace") {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                // the window goes to the workspace current at launch, even if it maps later
+                let workspace = self.wm_state.borrow().get_current_workspace_index();
+                (rest.trim_start(), Some(workspace))
+            }
+            _ => (arguments, None),
+        };
+        if command.is_empty() {
+            return Err(Error::InvalidArguments("exec".into(), arguments.into()));
+        }
+        self.spawn(command, workspace)
+    }
+
+    /// Run a command through the configured shell, detached from the WM.
+    /// With a workspace, the first window of the program is placed there.
+    /// Failures are broadcasted too, as bindings have nobody to reply to.
+    fn spawn(&self, command: &str, workspace: Option<usize>) -> Result<()> {
+        let startup_id = self.next_startup_id();
+        let pid = self.spawn_child(command, &startup_id)?;
+        if let Some(workspace) = workspace {
+            self.pending_spawns.borrow_mut().push(PendingSpawn {
+                pid,
+                startup_id,
+                workspace,
+                deadline: Instant::now() + SPAWN_TIMEOUT,
+            });
+            self.update_busy_cursor();
+        }
+        Ok(())
+    }
+
+    /// Start a command through the configured shell and return the process id of the shell.
+    fn spawn_child(&self, command: &str, startup_id: &str) -> Result<u32> {
+        let shell = self.config.borrow().get_shell().to_string();
+        let result = signals::detach_child(&mut Command::new(&shell))
+            .arg("-c")
+            .arg(command)
+            .env("DISPLAY", &self.display)
+            .env(ipc::SOCKET_ENV, self.ipc.path())
+            .env("DESKTOP_STARTUP_ID", startup_id)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
 
         match result {
-            Ok(_) => (),
-            Err(e) => panic!("{:?}", e),
+            Ok(child) => {
+                self.children
+                    .borrow_mut()
+                    .insert(child.id(), command.into());
+                Ok(child.id())
+            }
+            Err(e) => {
+                let error = format!("{}: {}", shell, e);
+                self.ipc.broadcast(&IpcEvent::SpawnFailed {
+                    command: command.into(),
+                    error: error.clone(),
+                });
+                Err(Error::Spawn(command.into(), error))
+            }
+        }
+    }
+
+    /// A startup notification id, unique to this WM and carrying the time
+    /// of the event that caused the launch, as the specification asks.
+    fn next_startup_id(&self) -> String {
+        let mut sequence = self.startup_sequence.borrow_mut();
+        *sequence += 1;
+        format!(
+            "{}-{}-{}_TIME{}",
+            PACKAGE_NAME,
+            std::process::id(),
+            sequence,
+            self.last_timestamp.borrow()
+        )
+    }
+
+    /// Wait for the spawned programs that exited, reporting the commands
+    /// the shell could not run.
+    fn reap_children(&self) {
+        for status in signals::reap_children() {
+            let Some(pid) = status.pid().map(|pid| pid.as_raw() as u32) else {
+                continue;
+            };
+            let Some(command) = self.children.borrow_mut().remove(&pid) else {
+                continue;
+            };
+            // a scratchpad program that exited will not show a window anymore
+            self.pending_scratchpads
+                .borrow_mut()
+                .retain(|s| s.pid != pid);
+            // the exit codes of a shell that did not find the program or could not run it
+            let error = match status {
+                WaitStatus::Exited(_, 127) => "command not found",
+                WaitStatus::Exited(_, 126) => "command cannot be run",
+                _ => continue,
+            };
+            warn!("Cannot run {}: {}", command, error);
+            self.pending_spawns.borrow_mut().retain(|s| s.pid != pid);
+            self.update_busy_cursor();
+            self.ipc.broadcast(&IpcEvent::SpawnFailed {
+                command,
+                error: error.into(),
+            });
         }
     }
 
@@ -606,3 +2807,167 @@ impl WM {
         println!("FocusOut: {}", event.event);
     }
 }
+
+/// Every combination of CapsLock and the modifiers NumLock and ScrollLock are bound to,
+/// none of them included. A lock bound to no modifier has a mask of 0.
+fn lock_mask_combinations((num_lock, scroll_lock): (u16, u16)) -> Vec<u16> {
+    let locks = [u16::from(ModMask::LOCK), num_lock, scroll_lock];
+    let mut combinations = vec![0];
+    for lock in locks.into_iter().filter(|&l| l != 0) {
+        let with_lock: Vec<u16> = combinations.iter().map(|c| c | lock).collect();
+        combinations.extend(with_lock);
+    }
+    combinations.sort_unstable();
+    combinations.dedup();
+    combinations
+}
+
+/// Server time carried by an event, if any.
+fn event_time(event: &Event) -> Option<Timestamp> {
+    match event {
+        Event::KeyPress(e) | Event::KeyRelease(e) => Some(e.time),
+        Event::ButtonPress(e) | Event::ButtonRelease(e) => Some(e.time),
+        Event::MotionNotify(e) => Some(e.time),
+        Event::EnterNotify(e) | Event::LeaveNotify(e) => Some(e.time),
+        Event::PropertyNotify(e) => Some(e.time),
+        _ => None,
+    }
+}
+
+/// The process and its ancestors, from the parent ids in /proc.
+fn process_ancestors(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    let mut pid = pid;
+    // the bound guards against looping on a bogus /proc
+    while pid > 1 && pids.len() < 64 {
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+            break;
+        };
+        let Some(parent) = parent_pid(&stat) else {
+            break;
+        };
+        pid = parent;
+        pids.push(pid);
+    }
+    pids
+}
+
+/// The parent id in the content of /proc/<pid>/stat, as in `42 (sh) S 1 42 ...`.
+fn parent_pid(stat: &str) -> Option<u32> {
+    // the command name in parentheses may contain spaces and parentheses
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().nth(1))
+        .and_then(|ppid| ppid.parse().ok())
+}
+
+/// Names of the outputs with a monitor connected, in the order of the server.
+fn connected_outputs(conn: &RustConnection, root: Window) -> Result<Vec<String>> {
+    let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+    let cookies = resources
+        .outputs
+        .iter()
+        .map(|output| conn.randr_get_output_info(*output, resources.config_timestamp))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let mut outputs = Vec::new();
+    for cookie in cookies {
+        let info = cookie.reply()?;
+        if info.connection == randr::Connection::CONNECTED {
+            outputs.push(String::from_utf8_lossy(&info.name).into_owned());
+        }
+    }
+    Ok(outputs)
+}
+
+/// Area of the screen shown by each enabled output, from left to right.
+/// Outputs mirroring each other count once.
+fn output_areas(conn: &RustConnection, root: Window) -> Result<Vec<(u32, u32, u32, u32)>> {
+    let resources = conn.randr_get_screen_resources_current(root)?.reply()?;
+    let cookies = resources
+        .crtcs
+        .iter()
+        .map(|crtc| conn.randr_get_crtc_info(*crtc, resources.config_timestamp))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let mut areas = Vec::new();
+    for cookie in cookies {
+        let info = cookie.reply()?;
+        if info.outputs.is_empty() || info.width == 0 || info.height == 0 {
+            continue;
+        }
+        let area = (
+            info.x.max(0) as u32,
+            info.y.max(0) as u32,
+            info.width as u32,
+            info.height as u32,
+        );
+        if !areas.contains(&area) {
+            areas.push(area);
+        }
+    }
+    areas.sort_by_key(|&(x, y, _, _)| (x, y));
+    Ok(areas)
+}
+
+/// Workspaces are numbered from 1, like the keys usually bound to them.
+fn parse_workspace(command: &str, number: &str) -> Result<usize> {
+    parse_argument::<usize>(command, number)?
+        .checked_sub(1)
+        .filter(|&i| i < NUM_WORKSPACES)
+        .ok_or_else(|| Error::InvalidArguments(command.into(), number.into()))
+}
+
+fn parse_argument<T: FromStr>(command: &str, argument: &str) -> Result<T> {
+    T::from_str(argument).map_err(|_| Error::InvalidArguments(command.into(), argument.into()))
+}
+
+/// Size of a frame without its border, X counts the border outside of the window size.
+fn inner_size(width: u32, height: u32) -> (u32, u32) {
+    let borders = 2 * FRAME_BORDER_WIDTH;
+    (
+        width.saturating_sub(borders).max(1),
+        height.saturating_sub(borders).max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_masks_combine_with_each_other() {
+        let lock = u16::from(ModMask::LOCK);
+        let (mod2, mod5) = (u16::from(ModMask::M2), u16::from(ModMask::M5));
+        assert_eq!(lock_mask_combinations((0, 0)), [0, lock]);
+        assert_eq!(
+            lock_mask_combinations((mod2, mod5)),
+            [
+                0,
+                lock,
+                mod2,
+                lock | mod2,
+                mod5,
+                lock | mod5,
+                mod2 | mod5,
+                lock | mod2 | mod5
+            ]
+        );
+        // NumLock and ScrollLock may share a modifier
+        assert_eq!(
+            lock_mask_combinations((mod2, mod2)),
+            [0, lock, mod2, lock | mod2]
+        );
+    }
+
+    #[test]
+    fn parent_pid_follows_the_command_name() {
+        assert_eq!(parent_pid("42 (sh) S 7 42 42 0 -1"), Some(7));
+        assert_eq!(parent_pid("42 (a (b) c) R 9 42 42 0 -1"), Some(9));
+        assert_eq!(parent_pid("42 (sh"), None);
+    }
+
+    #[test]
+    fn ancestors_start_with_the_process_and_its_parent() {
+        let pid = std::process::id();
+        let ancestors = process_ancestors(pid);
+        assert_eq!(ancestors[..2], [pid, std::os::unix::process::parent_id()]);
+    }
+}
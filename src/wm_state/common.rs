@@ -1,7 +1,143 @@
 use std::collections::VecDeque;
 
-use super::container::{Geometry, LayoutType};
+use strum_macros::EnumString;
+
+use super::container::{Container, Geometry, LayoutType};
 
 pub type FrameId = u32;
 // x11 window id
 pub type WindowId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    /// Whether moving this way goes towards the end of a list of children.
+    pub fn is_forward(&self) -> bool {
+        matches!(self, Direction::Right | Direction::Down)
+    }
+}
+
+/// Absolute rectangle of a container or an output, signed so that distances can be negative.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Rect {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+}
+
+impl Rect {
+    pub(super) fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x: x as i64,
+            y: y as i64,
+            width: width as i64,
+            height: height as i64,
+        }
+    }
+
+    pub(super) fn of(container: &Container) -> Self {
+        let (x, y) = container.get_position();
+        let (width, height) = container.get_dimensions();
+        Self::new(x, y, width, height)
+    }
+
+    /// Length of the rectangle along `direction`.
+    pub(super) fn span(&self, direction: Direction) -> i64 {
+        match direction {
+            Direction::Left | Direction::Right => self.width,
+            Direction::Up | Direction::Down => self.height,
+        }
+    }
+
+    /// The smallest rectangle holding both.
+    pub(super) fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        Rect {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    fn right(&self) -> i64 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y + self.height
+    }
+
+    fn center_x(&self) -> i64 {
+        self.x + self.width / 2
+    }
+
+    fn center_y(&self) -> i64 {
+        self.y + self.height / 2
+    }
+
+    fn overlaps_vertically(&self, other: &Rect) -> bool {
+        self.y < other.bottom() && other.y < self.bottom()
+    }
+
+    fn overlaps_horizontally(&self, other: &Rect) -> bool {
+        self.x < other.right() && other.x < self.right()
+    }
+}
+
+/// Of the `candidates`, the one next to `current` in `direction`: the closest one ahead
+/// that overlaps it across the direction, the most centered one first.
+/// With `wrap_span`, the length of the area searched along the direction, the ones behind
+/// are used too, ranked after every one ahead and the farthest first.
+pub(super) fn closest_in_direction<T>(
+    current: &Rect,
+    candidates: impl IntoIterator<Item = (Rect, T)>,
+    direction: Direction,
+    wrap_span: Option<i64>,
+) -> Option<T> {
+    candidates
+        .into_iter()
+        .filter_map(|(rect, candidate)| {
+            let (distance, overlaps, offset) = match direction {
+                Direction::Left => (
+                    current.x - rect.right(),
+                    current.overlaps_vertically(&rect),
+                    (current.center_y() - rect.center_y()).abs(),
+                ),
+                Direction::Right => (
+                    rect.x - current.right(),
+                    current.overlaps_vertically(&rect),
+                    (current.center_y() - rect.center_y()).abs(),
+                ),
+                Direction::Up => (
+                    current.y - rect.bottom(),
+                    current.overlaps_horizontally(&rect),
+                    (current.center_x() - rect.center_x()).abs(),
+                ),
+                Direction::Down => (
+                    rect.y - current.bottom(),
+                    current.overlaps_horizontally(&rect),
+                    (current.center_x() - rect.center_x()).abs(),
+                ),
+            };
+            if !overlaps {
+                return None;
+            }
+            match wrap_span {
+                _ if distance >= 0 => Some((distance, offset, candidate)),
+                // behind us: ranked after every candidate ahead, the farthest first
+                Some(span) => Some((distance + 2 * span, offset, candidate)),
+                None => None,
+            }
+        })
+        .min_by_key(|&(distance, offset, _)| (distance, offset))
+        .map(|(_, _, candidate)| candidate)
+}
@@ -3,9 +3,12 @@ use std::{
     slice::{Iter, IterMut},
 };
 
-use super::common::{FrameId, WindowId};
+use strum_macros::EnumString;
 
-#[derive(Debug)]
+use super::common::{Direction, FrameId, WindowId};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, EnumString)]
+#[strum(serialize_all = "lowercase")]
 pub enum LayoutType {
     Horizontal,
     Vertical,
@@ -42,6 +45,23 @@ impl LayoutType {
             },
         }
     }
+
+    /// Whether children of this layout are laid out along the direction.
+    pub fn is_along(&self, direction: Direction) -> bool {
+        match self {
+            LayoutType::Horizontal => matches!(direction, Direction::Left | Direction::Right),
+            LayoutType::Vertical => matches!(direction, Direction::Up | Direction::Down),
+            LayoutType::Floating | LayoutType::Tabbed => false,
+        }
+    }
+
+    /// Switch between the horizontal and vertical split layouts.
+    pub fn toggled(&self) -> LayoutType {
+        match self {
+            LayoutType::Horizontal => LayoutType::Vertical,
+            _ => LayoutType::Horizontal,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,6 +97,10 @@ pub struct Container {
     geometry: Geometry,
     is_repositioned: bool,
     remove_flag: bool,
+    // Set from the urgency hint or `_NET_WM_STATE_DEMANDS_ATTENTION` of the window
+    urgent: bool,
+    // Floating containers keep their own geometry and take no space in their parent
+    floating: bool,
     parent: Option<*mut Container>,
 }
 
@@ -115,6 +139,8 @@ impl Container {
             geometry,
             is_repositioned: false,
             remove_flag: false,
+            urgent: false,
+            floating: false,
         }
     }
     pub fn new(
@@ -131,24 +157,114 @@ impl Container {
     }
     pub fn add_child(&mut self, child: Container) -> &mut Container {
         self.children.push(child);
-        self.children.last_mut().unwrap().parent = Some(self as *mut Container);
+        self.relink();
         self.reposition();
         return self.children.last_mut().unwrap();
     }
 
-    /// Return the next focusing container
-    pub(super) fn get_next_focusing_container(&self, window_id: WindowId) -> Option<&Container> {
-        let window_index = self
+    pub(super) fn insert_child(&mut self, index: usize, child: Container) {
+        self.children.insert(index, child);
+        self.relink();
+    }
+
+    pub(super) fn take_child(&mut self, index: usize) -> Container {
+        let child = self.children.remove(index);
+        self.relink();
+        child
+    }
+
+    pub(super) fn swap_children(&mut self, a: usize, b: usize) {
+        self.children.swap(a, b);
+        self.relink();
+    }
+
+    /// Point the children, and theirs in turn, back at their parent.
+    /// Needed whenever a vector of children may have moved in memory.
+    pub(super) fn relink(&mut self) {
+        let self_ptr = self as *mut Container;
+        for child in self.children.iter_mut() {
+            child.parent = Some(self_ptr);
+            child.relink();
+        }
+    }
+
+    /// Drop the splits left without any window.
+    pub(super) fn remove_empty_splits(&mut self) {
+        for child in self.children.iter_mut() {
+            child.remove_empty_splits();
+        }
+        self.children
+            .retain(|c| c.main_win_id.is_some() || !c.children.is_empty());
+        self.relink();
+    }
+
+    pub(super) fn position_of(&self, child: *const Container) -> Option<usize> {
+        self.children.iter().position(|c| std::ptr::eq(c, child))
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.children.len()
+    }
+
+    pub(super) fn is_leaf(&self) -> bool {
+        self.main_win_id.is_some()
+    }
+
+    pub(super) fn layout_type(&self) -> LayoutType {
+        self.layout_type
+    }
+
+    pub(super) fn set_layout_type(&mut self, layout_type: LayoutType) {
+        self.layout_type = layout_type;
+    }
+
+    /// Whether the window, or any window in this subtree, needs attention.
+    pub fn is_urgent(&self) -> bool {
+        self.urgent || self.children.iter().any(|c| c.is_urgent())
+    }
+
+    pub(super) fn set_urgent(&mut self, urgent: bool) {
+        self.urgent = urgent;
+    }
+
+    pub fn is_floating(&self) -> bool {
+        self.floating
+    }
+
+    pub(super) fn set_floating(&mut self, floating: bool) {
+        self.floating = floating;
+    }
+
+    /// Place the container, and lay out its children in the new space.
+    pub(super) fn set_geometry(&mut self, geometry: Geometry) {
+        self.geometry = geometry;
+        self.is_repositioned = true;
+        self.reposition();
+    }
+
+    /// Every container holding a window in this subtree.
+    pub fn leaves(&self) -> Vec<&Container> {
+        if self.is_leaf() {
+            return vec![self];
+        }
+        self.children.iter().flat_map(|c| c.leaves()).collect()
+    }
+
+    /// Remove a window from anywhere in this subtree, returns whether it was found.
+    pub(super) fn remove_descendant(&mut self, window_id: WindowId) -> bool {
+        if let Some(index) = self
             .children
             .iter()
-            .position(|c| c.main_win_id == Some(window_id))?;
-
-        if self.children.len() == 1 {
-            return None;
-        } else {
-            let index = (window_index + 1) % (self.children.len());
-            return Some(&self.children[index]);
+            .position(|c| c.main_win_id == Some(window_id))
+        {
+            self.take_child(index);
+            self.remove_empty_splits();
+            self.reposition();
+            return true;
         }
+        self.children
+            .iter_mut()
+            .any(|c| c.remove_descendant(window_id))
     }
 
     pub(super) fn clean_removed_children(&mut self) {
@@ -273,17 +389,29 @@ impl Container {
     }
 
     pub fn reposition(&mut self) {
-        let live_children_count = self.iter().filter(|c| !c.remove_flag).count() as u32;
+        let live_children_count = self
+            .iter()
+            .filter(|c| !c.remove_flag && !c.floating)
+            .count() as u32;
         if live_children_count == 0 {
             return;
         }
         let child_width = self.geometry.width / live_children_count;
         let child_height = self.geometry.height / live_children_count;
-        let mut next_geometry = Geometry {
-            x: 0,
-            y: 0,
-            width: child_width,
-            height: self.geometry.height,
+        // children are placed in absolute coordinates, starting from the parent's corner
+        let mut next_geometry = match self.layout_type {
+            LayoutType::Vertical => Geometry {
+                x: self.geometry.x,
+                y: self.geometry.y,
+                width: self.geometry.width,
+                height: child_height,
+            },
+            _ => Geometry {
+                x: self.geometry.x,
+                y: self.geometry.y,
+                width: child_width,
+                height: self.geometry.height,
+            },
         };
         let unit = Geometry {
             x: child_width,
@@ -293,7 +421,7 @@ impl Container {
         };
         self.children
             .iter_mut()
-            .filter(|c| !c.remove_flag)
+            .filter(|c| !c.remove_flag && !c.floating)
             .for_each(|c| {
                 c.geometry = next_geometry;
                 c.reposition();
@@ -3,20 +3,38 @@ use std::collections::{HashMap, VecDeque};
 use log::info;
 
 use self::{
-    common::WindowId,
-    container::{Container, Geometry, LayoutType},
+    common::{closest_in_direction, FrameId, Rect, WindowId},
+    container::{Container, Geometry},
     workspace::Workspace,
 };
 
+pub use self::{common::Direction, container::LayoutType};
+
 mod common;
 mod container;
 mod workspace;
 
+/// A monitor, showing one workspace laid out over its area of the screen.
+struct Output {
+    area: (u32, u32, u32, u32),
+    workspace: usize,
+}
+
 pub struct WmState {
-    current_workspace: usize,
+    // Outputs from left to right, the first one covers the whole screen until they are known
+    outputs: Vec<Output>,
+    // The output with the focus, showing the current workspace
+    current_output: usize,
+    // Workspace commands act on instead of the current one, while the actions
+    // of a window rule run on a window that may not be shown
+    command_workspace: Option<usize>,
     // The number of workspaces
     num_workspaces: usize,
     workspaces: HashMap<usize, Workspace>,
+    // Containers hidden in the scratchpad, on no workspace
+    scratchpad: Vec<Container>,
+    // Windows sent to the scratchpad, hidden or shown, in the order they were sent
+    scratchpad_windows: Vec<WindowId>,
 }
 
 impl WmState {
@@ -26,14 +44,26 @@ impl WmState {
             workspaces.insert(i, Workspace::new(width, height));
         }
         Self {
-            current_workspace: 0,
+            outputs: vec![Output {
+                area: (0, 0, width, height),
+                workspace: 0,
+            }],
+            current_output: 0,
+            command_workspace: None,
             num_workspaces,
             workspaces,
+            scratchpad: Vec::new(),
+            scratchpad_windows: Vec::new(),
         }
     }
 
-    pub fn new_container(&mut self, client_win_id: u32, frame_win_id: u32) -> &mut Container {
-        let workspace = self.workspaces.get_mut(&self.current_workspace).unwrap();
+    pub fn new_container(
+        &mut self,
+        client_win_id: u32,
+        frame_win_id: u32,
+        workspace: usize,
+    ) -> &mut Container {
+        let workspace = self.workspaces.get_mut(&workspace).unwrap();
         let new_container = Container::new(
             frame_win_id,
             client_win_id,
@@ -49,39 +79,463 @@ impl WmState {
         workspace.change_layout(layout_type);
     }
 
-    pub fn remove_container(&mut self, window_id: WindowId) {
+    pub fn toggle_layout(&mut self) {
         let workspace = self.get_current_workspace_mut();
-        workspace.remove_container(window_id);
+        workspace.toggle_layout();
+    }
+
+    /// Put the focused container in a new split, see `Workspace::split`.
+    pub fn split(&mut self, layout_type: LayoutType) {
+        self.get_current_workspace_mut().split(layout_type);
+    }
+
+    /// Remove a window from whichever workspace it is on.
+    pub fn remove_container(&mut self, window_id: WindowId, focus_history: &[WindowId]) {
+        self.scratchpad_windows.retain(|&w| w != window_id);
+        let Some(index) = self.find_workspace(window_id) else {
+            return;
+        };
+        let workspace = self.workspaces.get_mut(&index).unwrap();
+        workspace.remove_container(window_id, focus_history);
+    }
+
+    /// Move the container of a window to another workspace, where it gets focused.
+    /// Returns the frames that moved.
+    pub fn move_window_to_workspace(
+        &mut self,
+        window_id: WindowId,
+        workspace: usize,
+        focus_history: &[WindowId],
+    ) -> Vec<FrameId> {
+        let Some(index) = self.find_workspace(window_id).filter(|&i| i != workspace) else {
+            return Vec::new();
+        };
+        let source = self.workspaces.get_mut(&index).unwrap();
+        let Some(container) = source.take_container(window_id, focus_history) else {
+            return Vec::new();
+        };
+        self.add_to_workspace(container, workspace)
+    }
+
+    /// Move the focused container, with its whole subtree, to another workspace.
+    /// Returns the frames that moved.
+    pub fn move_focused_container_to_workspace(
+        &mut self,
+        workspace: usize,
+        focus_history: &[WindowId],
+    ) -> Vec<FrameId> {
+        let current = self
+            .command_workspace
+            .unwrap_or(self.get_current_workspace_index());
+        if workspace == current {
+            return Vec::new();
+        }
+        let Some(container) = self
+            .get_current_workspace_mut()
+            .take_focused_container(focus_history)
+        else {
+            return Vec::new();
+        };
+        self.add_to_workspace(container, workspace)
+    }
+
+    fn add_to_workspace(&mut self, container: Container, workspace: usize) -> Vec<FrameId> {
+        let target = self.workspaces.get_mut(&workspace).unwrap();
+        target
+            .add_container(container)
+            .leaves()
+            .iter()
+            .filter_map(|c| c.frame_win_id)
+            .collect()
+    }
+
+    /// The workspace a window is on.
+    pub fn find_workspace(&self, window_id: WindowId) -> Option<usize> {
+        self.workspaces
+            .iter()
+            .find(|(_, workspace)| workspace.has_window(window_id))
+            .map(|(&index, _)| index)
     }
 
+    /// The workspace commands act on: the shown one, or the one of the command target.
     pub fn get_current_workspace(&self) -> &Workspace {
-        self.workspaces.get(&self.current_workspace).unwrap()
+        let index = self
+            .command_workspace
+            .unwrap_or(self.get_current_workspace_index());
+        self.workspaces.get(&index).unwrap()
     }
 
     pub fn get_current_workspace_mut(&mut self) -> &mut Workspace {
-        self.workspaces.get_mut(&self.current_workspace).unwrap()
+        let index = self
+            .command_workspace
+            .unwrap_or(self.get_current_workspace_index());
+        self.workspaces.get_mut(&index).unwrap()
+    }
+
+    /// Make commands act on a window, focused on its own workspace, without showing
+    /// that workspace. Returns the window focused there before, for `clear_command_target`.
+    pub fn set_command_target(&mut self, window_id: WindowId) -> Option<Option<WindowId>> {
+        let index = self.find_workspace(window_id)?;
+        self.command_workspace = Some(index);
+        let workspace = self.get_current_workspace_mut();
+        let previous_focus = workspace.get_focused_window();
+        workspace.set_current_focused_container(window_id);
+        Some(previous_focus)
+    }
+
+    /// Make commands act on the shown workspace again, once `previous_focus` is focused
+    /// back on the workspace of the target.
+    pub fn clear_command_target(&mut self, previous_focus: Option<WindowId>) {
+        if let Some(window_id) = previous_focus {
+            self.get_current_workspace_mut()
+                .set_current_focused_container(window_id);
+        }
+        self.command_workspace = None;
+    }
+
+    pub fn has_command_target(&self) -> bool {
+        self.command_workspace.is_some()
     }
 
     pub fn reposition(&mut self) {
-        let workspace = self.workspaces.get_mut(&self.current_workspace).unwrap();
-        workspace.reposition();
+        self.get_current_workspace_mut().reposition();
     }
 
+    /// Containers laid out anew on the shown workspaces.
     pub fn get_repositioned_containers(&self) -> Vec<&Container> {
-        return self.get_current_workspace().get_repositioned_children();
+        self.outputs
+            .iter()
+            .flat_map(|o| self.workspaces[&o.workspace].get_repositioned_children())
+            .collect()
     }
 
     pub fn get_removed_containers(&self) -> Vec<&Container> {
-        return self.get_current_workspace().get_removed_children();
+        self.workspaces
+            .values()
+            .flat_map(|w| w.get_removed_children())
+            .collect()
     }
 
     pub fn clean_removed_containers(&mut self) {
-        let workspace = self.workspaces.get_mut(&self.current_workspace).unwrap();
-        workspace.clean_removed_containers();
+        for workspace in self.workspaces.values_mut() {
+            workspace.clean_removed_containers();
+        }
     }
 
+    /// Show a workspace on the focused output, or focus the output already showing it.
     pub fn change_workspace(&mut self, workspace: usize) {
-        self.current_workspace = workspace;
+        if let Some(output) = self.outputs.iter().position(|o| o.workspace == workspace) {
+            self.current_output = output;
+            return;
+        }
+        let output = &mut self.outputs[self.current_output];
+        output.workspace = workspace;
+        let (x, y, width, height) = output.area;
+        self.workspaces
+            .get_mut(&workspace)
+            .unwrap()
+            .set_area(x, y, width, height);
+    }
+
+    pub fn get_current_workspace_index(&self) -> usize {
+        self.outputs[self.current_output].workspace
+    }
+
+    /// Whether a workspace is shown on any output.
+    pub fn is_workspace_shown(&self, workspace: usize) -> bool {
+        self.outputs.iter().any(|o| o.workspace == workspace)
+    }
+
+    /// Lay the workspaces out over the outputs, given by their area of the screen from
+    /// left to right. Outputs keep showing their workspace and the current workspace stays
+    /// shown, new outputs show the first workspaces not shown yet.
+    /// Returns the frames of the workspaces no longer shown, and of the newly shown ones.
+    pub fn set_outputs(&mut self, areas: &[(u32, u32, u32, u32)]) -> (Vec<FrameId>, Vec<FrameId>) {
+        let count = areas.len().min(self.num_workspaces);
+        if count == 0 {
+            return (Vec::new(), Vec::new());
+        }
+        let current = self.get_current_workspace_index();
+        let before: Vec<usize> = self.outputs.iter().map(|o| o.workspace).collect();
+        let mut shown: Vec<usize> = before.iter().copied().take(count).collect();
+        if !shown.contains(&current) {
+            shown[self.current_output.min(count - 1)] = current;
+        }
+        for workspace in 0..self.num_workspaces {
+            if shown.len() == count {
+                break;
+            }
+            if !shown.contains(&workspace) {
+                shown.push(workspace);
+            }
+        }
+
+        self.outputs = areas
+            .iter()
+            .zip(&shown)
+            .map(|(&area, &workspace)| Output { area, workspace })
+            .collect();
+        for output in &self.outputs {
+            let (x, y, width, height) = output.area;
+            self.workspaces
+                .get_mut(&output.workspace)
+                .unwrap()
+                .set_area(x, y, width, height);
+        }
+        self.current_output = shown.iter().position(|&w| w == current).unwrap();
+
+        let frames_of = |workspaces: Vec<usize>| -> Vec<FrameId> {
+            workspaces
+                .into_iter()
+                .flat_map(|w| self.workspaces[&w].get_frames())
+                .collect()
+        };
+        let hidden = before
+            .iter()
+            .copied()
+            .filter(|w| !shown.contains(w))
+            .collect();
+        let newly_shown = shown
+            .iter()
+            .copied()
+            .filter(|w| !before.contains(w))
+            .collect();
+        (frames_of(hidden), frames_of(newly_shown))
+    }
+
+    /// The output next to the focused one in `direction`.
+    fn output_in_direction(&self, direction: Direction) -> Option<usize> {
+        let rect = |output: &Output| {
+            let (x, y, width, height) = output.area;
+            Rect::new(x, y, width, height)
+        };
+        let current = rect(&self.outputs[self.current_output]);
+        let candidates = self
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != self.current_output)
+            .map(|(index, output)| (rect(output), index));
+        closest_in_direction(&current, candidates, direction, None)
+    }
+
+    /// The workspaces shown on the outputs without the focus.
+    fn other_shown_workspaces(&self) -> Vec<&Workspace> {
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| index != self.current_output)
+            .map(|(_, output)| &self.workspaces[&output.workspace])
+            .collect()
+    }
+
+    pub fn get_num_workspaces(&self) -> usize {
+        self.num_workspaces
+    }
+
+    pub fn set_urgent(&mut self, window_id: WindowId, urgent: bool) {
+        let Some(index) = self.find_workspace(window_id) else {
+            return;
+        };
+        let workspace = self.workspaces.get_mut(&index).unwrap();
+        workspace.set_urgent(window_id, urgent);
+    }
+
+    /// Workspaces with a window that needs attention, in order.
+    pub fn get_urgent_workspaces(&self) -> Vec<usize> {
+        let mut urgent_workspaces: Vec<usize> = self
+            .workspaces
+            .iter()
+            .filter(|(_, w)| w.is_urgent())
+            .map(|(&index, _)| index)
+            .collect();
+        urgent_workspaces.sort_unstable();
+        urgent_workspaces
+    }
+
+    /// An urgent window, from the first urgent workspace.
+    pub fn find_urgent_window(&self) -> Option<WindowId> {
+        let index = *self.get_urgent_workspaces().first()?;
+        self.workspaces[&index]
+            .get_urgent_containers()
+            .first()?
+            .main_win_id
+    }
+
+    pub fn get_urgent_frames(&self) -> Vec<FrameId> {
+        self.workspaces
+            .values()
+            .flat_map(|w| w.get_urgent_containers())
+            .filter_map(|c| c.frame_win_id)
+            .collect()
+    }
+
+    pub fn set_floating(&mut self, floating: bool, focus_history: &[WindowId]) {
+        self.get_current_workspace_mut()
+            .set_floating(floating, focus_history);
+    }
+
+    pub fn toggle_floating(&mut self, focus_history: &[WindowId]) {
+        let floating = self.get_current_workspace().is_focused_floating();
+        self.set_floating(!floating, focus_history);
+    }
+
+    pub fn resize_floating(&mut self, width: u32, height: u32) {
+        self.get_current_workspace_mut()
+            .resize_floating(width, height);
+    }
+
+    pub fn move_floating(&mut self, x: u32, y: u32) {
+        self.get_current_workspace_mut().move_floating(x, y);
+    }
+
+    /// Position and size of the focused container, if it is floating.
+    pub fn get_floating_geometry(&self) -> Option<(u32, u32, u32, u32)> {
+        self.get_current_workspace().get_floating_geometry()
+    }
+
+    /// Frames of the floating windows on the shown workspaces.
+    pub fn get_floating_frames(&self) -> Vec<FrameId> {
+        self.outputs
+            .iter()
+            .flat_map(|o| self.workspaces[&o.workspace].get_floating_frames())
+            .collect()
+    }
+
+    /// Hide the focused container in the scratchpad. Returns the frames to unmap.
+    /// Until it is shown again, it floats with half the size of the workspace.
+    pub fn move_focused_to_scratchpad(&mut self, focus_history: &[WindowId]) -> Vec<FrameId> {
+        let workspace = self.get_current_workspace_mut();
+        let Some(container) = workspace.take_focused_container(focus_history) else {
+            return Vec::new();
+        };
+        self.float_in_scratchpad(container, None)
+    }
+
+    /// Hide the container of a window in the scratchpad, floating with the given size
+    /// or half the size of the workspace. Returns the frames to unmap.
+    pub fn move_window_to_scratchpad(
+        &mut self,
+        window_id: WindowId,
+        size: Option<(u32, u32)>,
+        focus_history: &[WindowId],
+    ) -> Vec<FrameId> {
+        let Some(index) = self.find_workspace(window_id) else {
+            return Vec::new();
+        };
+        let workspace = self.workspaces.get_mut(&index).unwrap();
+        let Some(container) = workspace.take_container(window_id, focus_history) else {
+            return Vec::new();
+        };
+        self.float_in_scratchpad(container, size)
+    }
+
+    fn float_in_scratchpad(
+        &mut self,
+        mut container: Container,
+        size: Option<(u32, u32)>,
+    ) -> Vec<FrameId> {
+        let workspace = self.get_current_workspace();
+        let (width, height) = size.unwrap_or_else(|| {
+            let (width, height) = workspace.get_dimensions();
+            (width / 2, height / 2)
+        });
+        container.set_floating(true);
+        container.set_geometry(workspace.get_centered_geometry(width, height));
+        self.hide_in_scratchpad(container)
+    }
+
+    /// Hide the scratchpad container of a window shown on a workspace.
+    /// Returns the frames to unmap.
+    pub fn hide_scratchpad_window(
+        &mut self,
+        window_id: WindowId,
+        focus_history: &[WindowId],
+    ) -> Vec<FrameId> {
+        let Some(index) = self.find_workspace(window_id) else {
+            return Vec::new();
+        };
+        let workspace = self.workspaces.get_mut(&index).unwrap();
+        let Some(container) = workspace.take_container(window_id, focus_history) else {
+            return Vec::new();
+        };
+        self.hide_in_scratchpad(container)
+    }
+
+    fn hide_in_scratchpad(&mut self, container: Container) -> Vec<FrameId> {
+        let leaves = container.leaves();
+        for window_id in leaves.iter().filter_map(|c| c.main_win_id) {
+            if !self.scratchpad_windows.contains(&window_id) {
+                self.scratchpad_windows.push(window_id);
+            }
+        }
+        let frames = leaves.iter().filter_map(|c| c.frame_win_id).collect();
+        self.scratchpad.push(container);
+        frames
+    }
+
+    /// Show the scratchpad container of a window floating in the middle of the current
+    /// workspace, whether it was hidden or shown on another workspace.
+    /// Returns the frames to map.
+    pub fn show_scratchpad_window(
+        &mut self,
+        window_id: WindowId,
+        focus_history: &[WindowId],
+    ) -> Vec<FrameId> {
+        let hidden = self.scratchpad.iter().position(|c| {
+            c.leaves()
+                .iter()
+                .any(|leaf| leaf.main_win_id == Some(window_id))
+        });
+        let container = match hidden {
+            Some(index) => self.scratchpad.remove(index),
+            None => {
+                let Some(index) = self.find_workspace(window_id) else {
+                    return Vec::new();
+                };
+                let workspace = self.workspaces.get_mut(&index).unwrap();
+                let Some(container) = workspace.take_container(window_id, focus_history) else {
+                    return Vec::new();
+                };
+                container
+            }
+        };
+        let frames = container
+            .leaves()
+            .iter()
+            .filter_map(|c| c.frame_win_id)
+            .collect();
+        self.get_current_workspace_mut().add_floating(container);
+        frames
+    }
+
+    pub fn get_scratchpad_windows(&self) -> Vec<WindowId> {
+        self.scratchpad_windows.clone()
+    }
+
+    /// Forget a window hidden in the scratchpad, returns whether it was there.
+    pub fn remove_from_scratchpad(&mut self, window_id: WindowId) -> bool {
+        let Some(index) = self.scratchpad.iter().position(|c| {
+            c.leaves()
+                .iter()
+                .any(|leaf| leaf.main_win_id == Some(window_id))
+        }) else {
+            return false;
+        };
+        self.scratchpad_windows.retain(|&w| w != window_id);
+        let container = &mut self.scratchpad[index];
+        if container.is_leaf()
+            || !container.remove_descendant(window_id)
+            || container.leaves().is_empty()
+        {
+            self.scratchpad.remove(index);
+        }
+        true
+    }
+
+    /// Frames of the windows on the current workspace.
+    pub fn get_current_frames(&self) -> Vec<FrameId> {
+        self.workspaces[&self.get_current_workspace_index()].get_frames()
     }
 
     pub fn set_focusing_container(&mut self, window_id: WindowId) {
@@ -89,21 +543,149 @@ impl WmState {
         workspace.set_current_focused_container(window_id);
     }
 
+    /// Focus nothing on the current workspace.
+    pub fn unset_focusing_container(&mut self) {
+        let workspace = self.get_current_workspace_mut();
+        workspace.unset_current_focused_container();
+    }
+
+    /// The focused container, either a window or a split of windows.
     pub fn get_focusing_container(&self) -> Option<&Container> {
-        let workspace = self.workspaces.get(&self.current_workspace).unwrap();
-        let current_focused_container = unsafe { &*workspace.current_focused_container };
+        let workspace = self.get_current_workspace();
+        workspace.get_focused_window()?;
+        let current_focused_container = workspace.get_current_focused_container();
         info!(
             "current focused container: {:#?}",
             current_focused_container
         );
-        if current_focused_container.frame_win_id.is_some() {
-            return Some(current_focused_container);
+        Some(current_focused_container)
+    }
+
+    /// The window that gets the input focus. When a split is focused,
+    /// this is the window in it that was focused last.
+    pub fn get_focused_window(&self) -> Option<WindowId> {
+        self.get_current_workspace().get_focused_window()
+    }
+
+    pub fn focus_parent(&mut self) {
+        self.get_current_workspace_mut().focus_parent();
+    }
+
+    pub fn focus_child(&mut self) {
+        self.get_current_workspace_mut().focus_child();
+    }
+
+    /// The window next to the focused container in `direction`, if any, on the current
+    /// workspace or on the workspaces shown on the other outputs.
+    pub fn find_window_in_direction(&self, direction: Direction, wrap: bool) -> Option<WindowId> {
+        // a command target is on a workspace that may not be shown
+        let others = match self.command_workspace {
+            Some(_) => Vec::new(),
+            None => self.other_shown_workspaces(),
+        };
+        self.get_current_workspace()
+            .find_window_in_direction(&others, direction, wrap)
+    }
+
+    /// Move the focused container in `direction`. At the edge of the workspace, it goes
+    /// on to the workspace of the next output that way, which gets the focus.
+    pub fn move_focused_container(&mut self, direction: Direction, focus_history: &[WindowId]) {
+        if self.get_current_workspace_mut().move_container(direction)
+            || self.command_workspace.is_some()
+        {
+            return;
         }
+        let Some(output) = self.output_in_direction(direction) else {
+            return;
+        };
+        let Some(container) = self
+            .get_current_workspace_mut()
+            .take_focused_container(focus_history)
+        else {
+            return;
+        };
+        self.current_output = output;
+        let workspace = self.get_current_workspace_mut();
+        if container.is_floating() {
+            workspace.add_floating(container);
+        } else {
+            workspace.add_container(container);
+        }
+    }
+}
 
-        return None;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two outputs side by side, window 1 on the first workspace and 2 on the second.
+    fn state_on_two_outputs() -> WmState {
+        let mut wm_state = WmState::new(10, 1000, 500);
+        wm_state.new_container(1, 101, 0);
+        wm_state.set_outputs(&[(0, 0, 1000, 500), (1000, 0, 800, 500)]);
+        wm_state.new_container(2, 102, 1);
+        wm_state
+    }
+
+    fn geometry(wm_state: &WmState, window: WindowId) -> (u32, u32, u32, u32) {
+        let workspace = &wm_state.workspaces[&wm_state.find_workspace(window).unwrap()];
+        // every container laid out so far, they are never unmarked
+        let container = workspace
+            .get_repositioned_children()
+            .into_iter()
+            .find(|c| c.main_win_id == Some(window))
+            .unwrap();
+        let ((x, y), (width, height)) = (container.get_position(), container.get_dimensions());
+        (x, y, width, height)
+    }
+
+    #[test]
+    fn each_output_shows_a_workspace_over_its_area() {
+        let wm_state = state_on_two_outputs();
+
+        assert_eq!(wm_state.get_current_workspace_index(), 0);
+        assert!(wm_state.is_workspace_shown(1));
+        assert!(!wm_state.is_workspace_shown(2));
+        assert_eq!(geometry(&wm_state, 1), (0, 0, 1000, 500));
+        assert_eq!(geometry(&wm_state, 2), (1000, 0, 800, 500));
+    }
+
+    #[test]
+    fn changing_to_a_shown_workspace_focuses_its_output() {
+        let mut wm_state = state_on_two_outputs();
+        wm_state.change_workspace(1);
+        wm_state.change_workspace(2);
+
+        assert!(wm_state.is_workspace_shown(0));
+        assert!(!wm_state.is_workspace_shown(1));
+        assert_eq!(wm_state.get_current_workspace_index(), 2);
     }
 
-    pub fn move_window_to_left(&mut self, client_win_id: u32) {}
+    #[test]
+    fn focus_and_move_cross_outputs() {
+        let mut wm_state = state_on_two_outputs();
+        wm_state.set_focusing_container(1);
 
-    pub fn move_window_to_right(&mut self, client_win_id: u32) {}
+        assert_eq!(
+            wm_state.find_window_in_direction(Direction::Right, false),
+            Some(2)
+        );
+        wm_state.move_focused_container(Direction::Right, &[1, 2]);
+        assert_eq!(wm_state.get_current_workspace_index(), 1);
+        assert_eq!(wm_state.get_focused_window(), Some(1));
+        assert_eq!(geometry(&wm_state, 2), (1000, 0, 400, 500));
+        assert_eq!(geometry(&wm_state, 1), (1400, 0, 400, 500));
+    }
+
+    #[test]
+    fn workspace_of_a_disconnected_output_is_hidden() {
+        let mut wm_state = state_on_two_outputs();
+        wm_state.change_workspace(1);
+        let (hidden, shown) = wm_state.set_outputs(&[(0, 0, 1000, 500)]);
+
+        assert_eq!(hidden, vec![101]);
+        assert!(shown.is_empty());
+        assert_eq!(wm_state.get_current_workspace_index(), 1);
+        assert_eq!(geometry(&wm_state, 2), (0, 0, 1000, 500));
+    }
 }
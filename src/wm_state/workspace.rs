@@ -3,7 +3,7 @@ use std::collections::VecDeque;
 use log::info;
 
 use super::{
-    common::{FrameId, WindowId},
+    common::{closest_in_direction, Direction, FrameId, Rect, WindowId},
     container::{Container, Geometry, LayoutType},
 };
 
@@ -12,10 +12,13 @@ pub struct Workspace {
     // Top -> ... -> Bottom
     //  0  -> ... ->  n
     display_stack: VecDeque<Container>,
-    /// Current focused client
-    /// A focused client is always a parent frame that binded to a container
-    /// or a application window, both of them are framable
-    pub(super) current_focused_container: *mut Container,
+    /// Window of the focused container, or of a container inside the focused split.
+    /// None when nothing is focused.
+    focused_window: Option<WindowId>,
+    /// How many levels above the container of `focused_window` the focus is.
+    /// Keeping the focus as a window rather than a pointer lets it survive
+    /// containers moving around in the tree.
+    focus_depth: usize,
     // root container
     container: Container,
 }
@@ -26,40 +29,148 @@ impl Workspace {
             LayoutType::Horizontal,
             Geometry::new(0, 0, width, height),
         );
-        let focus_pointer = &container as *const Container as *mut Container;
         Self {
             display_stack: VecDeque::new(),
             container,
-            current_focused_container: focus_pointer,
+            focused_window: None,
+            focus_depth: 0,
         }
     }
     pub fn reposition(&mut self) {
         self.container.reposition();
     }
 
-    pub(super) fn remove_container(&mut self, window_id: u32) {
+    /// Remove a window. If it was focused, the focus goes to the window of its split
+    /// that was focused most recently according to `focus_history`, or else to the
+    /// most recent one of the workspace.
+    pub(super) fn remove_container(&mut self, window_id: u32, focus_history: &[WindowId]) {
         let root_container = &mut self.container as *mut Container;
         let parent_container =
             Self::find_parent_container(root_container, &mut |c| c.main_win_id == Some(window_id));
         if let Some(parent_container) = parent_container {
             unsafe {
                 let parent_container = &mut *parent_container;
-                let next_focusing_container =
-                    parent_container.get_next_focusing_container(window_id);
-                self.current_focused_container =
-                    if let Some(next_focusing_container) = next_focusing_container {
-                        next_focusing_container as *const Container as *mut Container
-                    } else if parent_container.is_child() {
-                        parent_container.get_parent() as *const Container as *mut Container
-                    } else {
-                        root_container
-                    };
-
+                self.hand_over_focus(parent_container, &[window_id], focus_history);
                 parent_container.remove_window(window_id);
             }
         }
     }
 
+    /// Move the focus away from windows leaving the tree, if it is on one of them.
+    /// It goes to the window of `parent` focused most recently, or else to the most
+    /// recent one of the workspace.
+    fn hand_over_focus(
+        &mut self,
+        parent: &Container,
+        removed: &[WindowId],
+        focus_history: &[WindowId],
+    ) {
+        if !self.focused_window.is_some_and(|w| removed.contains(&w)) {
+            return;
+        }
+        let window_ids = |container: &Container| -> Vec<WindowId> {
+            container
+                .leaves()
+                .iter()
+                .filter_map(|c| c.main_win_id)
+                .filter(|w| !removed.contains(w))
+                .collect()
+        };
+        let siblings = window_ids(parent);
+        let others = window_ids(&self.container);
+        let most_recent = |candidates: &[WindowId]| {
+            focus_history
+                .iter()
+                .copied()
+                .find(|w| candidates.contains(w))
+        };
+        self.focused_window = most_recent(&siblings)
+            .or_else(|| most_recent(&others))
+            .or_else(|| others.first().copied());
+        self.focus_depth = 0;
+    }
+
+    /// Detach the container of a window from the tree, to be added to another one.
+    pub(super) fn take_container(
+        &mut self,
+        window_id: WindowId,
+        focus_history: &[WindowId],
+    ) -> Option<Container> {
+        let container = self.find_container_mut(window_id)? as *mut Container;
+        Some(self.take(container, focus_history))
+    }
+
+    /// Detach the focused container, with its whole subtree, from the tree.
+    pub(super) fn take_focused_container(
+        &mut self,
+        focus_history: &[WindowId],
+    ) -> Option<Container> {
+        let (container, _) = self.resolve_focus()?;
+        Some(self.take(container, focus_history))
+    }
+
+    fn take(&mut self, container: *mut Container, focus_history: &[WindowId]) -> Container {
+        unsafe {
+            let parent = (*container).get_parent();
+            let removed: Vec<WindowId> = (*container)
+                .leaves()
+                .iter()
+                .filter_map(|c| c.main_win_id)
+                .collect();
+            self.hand_over_focus(&*parent, &removed, focus_history);
+            let index = (*parent).position_of(container).unwrap();
+            let taken = (*parent).take_child(index);
+            self.container.remove_empty_splits();
+            self.container.relink();
+            self.container.reposition();
+            taken
+        }
+    }
+
+    pub(super) fn has_window(&self, window_id: WindowId) -> bool {
+        self.container
+            .leaves()
+            .iter()
+            .any(|c| c.main_win_id == Some(window_id))
+    }
+
+    fn find_container_mut(&mut self, window_id: WindowId) -> Option<&mut Container> {
+        let root_container = &mut self.container as *mut Container;
+        let parent_container =
+            Self::find_parent_container(root_container, &|c| c.main_win_id == Some(window_id))?;
+        unsafe { &mut *parent_container }
+            .iter_mut()
+            .find(|c| c.main_win_id == Some(window_id))
+    }
+
+    pub(super) fn set_urgent(&mut self, window_id: WindowId, urgent: bool) {
+        if let Some(container) = self.find_container_mut(window_id) {
+            container.set_urgent(urgent);
+        }
+    }
+
+    /// Whether any window on the workspace needs attention.
+    pub(super) fn is_urgent(&self) -> bool {
+        self.container.is_urgent()
+    }
+
+    pub(super) fn get_urgent_containers(&self) -> Vec<&Container> {
+        self.container
+            .leaves()
+            .into_iter()
+            .filter(|c| c.is_urgent())
+            .collect()
+    }
+
+    /// Frames of every window on the workspace.
+    pub(super) fn get_frames(&self) -> Vec<FrameId> {
+        self.container
+            .leaves()
+            .iter()
+            .filter_map(|c| c.frame_win_id)
+            .collect()
+    }
+
     // get containers that need to be removed
     // for the X server to clean the corresponding frames
     fn get_removed_containers(&self) -> Vec<&Container> {
@@ -70,6 +181,59 @@ impl Workspace {
     pub(super) fn clean_removed_containers(&mut self) {
         self.container.unmark_removed();
         self.container.clean_removed_children();
+        self.container.relink();
+    }
+
+    /// The focused container, or the root container when nothing is focused.
+    pub(super) fn get_current_focused_container(&self) -> &Container {
+        match self.resolve_focus() {
+            Some((container, _)) => unsafe { &*container },
+            None => &self.container,
+        }
+    }
+
+    pub(super) fn get_focused_window(&self) -> Option<WindowId> {
+        self.focused_window
+    }
+
+    /// Find the focused container in the current tree, along with how many levels
+    /// above `focused_window` it actually is. The root container is never focused.
+    fn resolve_focus(&self) -> Option<(*mut Container, usize)> {
+        let window_id = self.focused_window?;
+        let leaves = self.container.leaves();
+        let leaf = leaves.iter().find(|c| c.main_win_id == Some(window_id))?;
+        let mut container = *leaf as *const Container as *mut Container;
+        let mut depth = 0;
+        unsafe {
+            while depth < self.focus_depth {
+                match (*container).try_get_parent() {
+                    Some(parent) if (*parent).is_child() => container = parent,
+                    _ => break,
+                }
+                depth += 1;
+            }
+        }
+        Some((container, depth))
+    }
+
+    /// Focus the split containing the focused container.
+    pub(super) fn focus_parent(&mut self) {
+        let Some((container, depth)) = self.resolve_focus() else {
+            return;
+        };
+        let has_parent = unsafe {
+            matches!((*container).try_get_parent(), Some(parent) if (*parent).is_child())
+        };
+        if has_parent {
+            self.focus_depth = depth + 1;
+        }
+    }
+
+    /// Focus the child of the focused split that leads to the last focused window.
+    pub(super) fn focus_child(&mut self) {
+        if let Some((_, depth)) = self.resolve_focus() {
+            self.focus_depth = depth.saturating_sub(1);
+        }
     }
 
     fn find_parent_container<'a>(
@@ -80,17 +244,17 @@ impl Workspace {
             let root = &mut *root;
             let mut found = Err(());
 
-            for (i, child) in root.iter_mut().enumerate() {
+            for child in root.iter_mut() {
                 if pred(child) {
                     found = Ok(None);
                     break;
-                } else if Self::find_parent_container(child, pred).is_some() {
-                    found = Ok(Some(i));
+                } else if let Some(parent) = Self::find_parent_container(child, pred) {
+                    found = Ok(Some(parent));
                     break;
                 }
             }
             match found {
-                Ok(Some(i)) => Some(&mut root[i]),
+                Ok(Some(parent)) => Some(parent),
                 Ok(None) => Some(root),
                 Err(()) => None,
             }
@@ -98,34 +262,248 @@ impl Workspace {
     }
 
     pub(super) fn add_container<'a>(&'a mut self, new_container: Container) -> &'a mut Container {
-        let root_container = &mut self.container;
-        let parent_container = unsafe {
-            let focusing_container = &mut *self.current_focused_container;
-            if focusing_container.is_child() {
-                focusing_container.get_parent()
-            } else {
-                root_container
-            }
-        };
+        // next to the focused window, or inside the focused split
+        let parent_container = self.get_focused_split();
         info!("root container: {:#?}", parent_container);
 
         let added_container = unsafe { &mut *parent_container }.add_child(new_container);
-        self.current_focused_container = added_container as *mut Container;
+        // a split moved from another workspace is focused through its first window
+        self.focused_window = added_container.leaves().first().and_then(|c| c.main_win_id);
+        self.focus_depth = 0;
         added_container
     }
 
     pub(super) fn set_current_focused_container(&mut self, window_id: WindowId) {
-        let root_container = &mut self.container as *mut Container;
-        let Some(parent_container) =
-            Self::find_parent_container(root_container, &mut |c| c.main_win_id == Some(window_id)) else {return;};
-        let parent_container = unsafe { &mut *parent_container };
-        let Some(container) = parent_container
+        if !self.has_window(window_id) {
+            return;
+        }
+        self.focused_window = Some(window_id);
+        self.focus_depth = 0;
+    }
+
+    pub(super) fn unset_current_focused_container(&mut self) {
+        self.focused_window = None;
+        self.focus_depth = 0;
+    }
+
+    /// Find the window next to the focused container in `direction`, by geometry rather
+    /// than by position in the tree, so that split boundaries are crossed. The windows of
+    /// `others`, the workspaces shown on the other outputs, are searched too.
+    /// With `wrap`, the farthest window on the other side is used at the edge.
+    pub(super) fn find_window_in_direction(
+        &self,
+        others: &[&Workspace],
+        direction: Direction,
+        wrap: bool,
+    ) -> Option<WindowId> {
+        let (focused, _) = self.resolve_focus()?;
+        let focused = unsafe { &*focused };
+        let inside: Vec<Option<WindowId>> =
+            focused.leaves().iter().map(|c| c.main_win_id).collect();
+        let workspaces: Vec<&Workspace> = std::iter::once(self)
+            .chain(others.iter().copied())
+            .collect();
+        let screen = workspaces
+            .iter()
+            .map(|w| Rect::of(&w.container))
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let candidates = workspaces
             .iter()
-            .find(|&c| c.main_win_id == Some(window_id))
-            .map(|c| c as *const Container as *mut Container) else {
-                return
-            };
-        self.current_focused_container = container;
+            .flat_map(|w| w.container.leaves())
+            .filter(|c| !inside.contains(&c.main_win_id))
+            .filter_map(|c| Some((Rect::of(c), c.main_win_id?)));
+
+        closest_in_direction(
+            &Rect::of(focused),
+            candidates,
+            direction,
+            wrap.then_some(screen.span(direction)),
+        )
+    }
+
+    /// Move the focused container, with its whole subtree, in `direction`: swap it with
+    /// its sibling, enter the neighbouring split, or leave its split for the closest
+    /// ancestor laid out along the direction. Returns false when it is at the edge of the
+    /// workspace, where it did not move.
+    pub(super) fn move_container(&mut self, direction: Direction) -> bool {
+        let Some((moving, _)) = self.resolve_focus() else {
+            return false;
+        };
+
+        unsafe {
+            let moving_parent = (*moving).get_parent();
+            let moving_index = (&*moving_parent).position_of(moving).unwrap();
+            let mut node = moving;
+
+            loop {
+                let Some(parent) = (*node).try_get_parent() else {
+                    // reached the edge of the workspace
+                    return false;
+                };
+                let parent = &mut *parent;
+                let index = parent.position_of(node).unwrap();
+
+                if parent.layout_type().is_along(direction) {
+                    if node == moving {
+                        let target = if direction.is_forward() {
+                            Some(index + 1).filter(|&i| i < parent.len())
+                        } else {
+                            index.checked_sub(1)
+                        };
+                        if let Some(target) = target {
+                            if parent[target].is_leaf() {
+                                parent.swap_children(index, target);
+                            } else {
+                                let moved = parent.take_child(index);
+                                let target = if target > index { target - 1 } else { target };
+                                let split = &mut parent[target];
+                                let split_index = if direction.is_forward() {
+                                    0
+                                } else {
+                                    split.len()
+                                };
+                                split.insert_child(split_index, moved);
+                            }
+                            break;
+                        }
+                    } else {
+                        // the container is at the edge of its split, move it out next to the split
+                        let moved = (&mut *moving_parent).take_child(moving_index);
+                        let index = if direction.is_forward() {
+                            index + 1
+                        } else {
+                            index
+                        };
+                        parent.insert_child(index, moved);
+                        break;
+                    }
+                }
+                node = parent;
+            }
+        }
+
+        self.container.remove_empty_splits();
+        self.container.relink();
+        self.container.reposition();
+        true
+    }
+
+    /// Float the focused container above the tiled ones, or tile it back.
+    /// It keeps the place it had in the tiling when it starts floating.
+    pub(super) fn set_floating(&mut self, floating: bool, focus_history: &[WindowId]) {
+        let Some((focused, _)) = self.resolve_focus() else {
+            return;
+        };
+        if unsafe { (*focused).is_floating() } == floating {
+            return;
+        }
+        let (focused_window, focus_depth) = (self.focused_window, self.focus_depth);
+        let mut container = self.take(focused, focus_history);
+        container.set_floating(floating);
+        if floating {
+            self.container.add_child(container);
+        } else {
+            self.add_container(container);
+        }
+        self.focused_window = focused_window;
+        self.focus_depth = focus_depth;
+    }
+
+    pub(super) fn is_focused_floating(&self) -> bool {
+        self.resolve_focus()
+            .is_some_and(|(focused, _)| unsafe { (*focused).is_floating() })
+    }
+
+    /// Resize the focused container if it is floating, keeping its position.
+    pub(super) fn resize_floating(&mut self, width: u32, height: u32) {
+        let Some((focused, _)) = self.resolve_focus() else {
+            return;
+        };
+        let container = unsafe { &mut *focused };
+        if !container.is_floating() {
+            return;
+        }
+        let (x, y) = container.get_position();
+        container.set_geometry(Geometry::new(x, y, width, height));
+    }
+
+    /// Move the focused container if it is floating, keeping its size.
+    pub(super) fn move_floating(&mut self, x: u32, y: u32) {
+        let Some((focused, _)) = self.resolve_focus() else {
+            return;
+        };
+        let container = unsafe { &mut *focused };
+        if !container.is_floating() {
+            return;
+        }
+        let (width, height) = container.get_dimensions();
+        container.set_geometry(Geometry::new(x, y, width, height));
+    }
+
+    /// Position and size of the focused container if it is floating.
+    pub(super) fn get_floating_geometry(&self) -> Option<(u32, u32, u32, u32)> {
+        let (focused, _) = self.resolve_focus()?;
+        let container = unsafe { &*focused };
+        if !container.is_floating() {
+            return None;
+        }
+        let ((x, y), (width, height)) = (container.get_position(), container.get_dimensions());
+        Some((x, y, width, height))
+    }
+
+    /// Add a floating container in the middle of the workspace, keeping its size, and focus it.
+    pub(super) fn add_floating(&mut self, mut container: Container) {
+        let (width, height) = container.get_dimensions();
+        container.set_floating(true);
+        container.set_geometry(self.get_centered_geometry(width, height));
+        let added_container = self.container.add_child(container);
+        self.focused_window = added_container.leaves().first().and_then(|c| c.main_win_id);
+        self.focus_depth = 0;
+    }
+
+    pub(super) fn get_dimensions(&self) -> (u32, u32) {
+        self.container.get_dimensions()
+    }
+
+    /// Lay the workspace out over the area of the output showing it. Floating containers
+    /// keep their place relative to the workspace.
+    pub(super) fn set_area(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let (old_x, old_y) = self.container.get_position();
+        for child in self.container.iter_mut().filter(|c| c.is_floating()) {
+            let (child_x, child_y) = child.get_position();
+            let (child_width, child_height) = child.get_dimensions();
+            child.set_geometry(Geometry::new(
+                child_x.saturating_sub(old_x) + x,
+                child_y.saturating_sub(old_y) + y,
+                child_width,
+                child_height,
+            ));
+        }
+        self.container
+            .set_geometry(Geometry::new(x, y, width, height));
+    }
+
+    pub(super) fn get_centered_geometry(&self, width: u32, height: u32) -> Geometry {
+        let (x, y) = self.container.get_position();
+        let (workspace_width, workspace_height) = self.container.get_dimensions();
+        let (width, height) = (width.min(workspace_width), height.min(workspace_height));
+        Geometry::new(
+            x + (workspace_width - width) / 2,
+            y + (workspace_height - height) / 2,
+            width,
+            height,
+        )
+    }
+
+    /// Frames of the floating windows, to be kept above the tiled ones.
+    pub(super) fn get_floating_frames(&self) -> Vec<FrameId> {
+        self.container
+            .iter()
+            .filter(|c| c.is_floating())
+            .flat_map(|c| c.leaves())
+            .filter_map(|c| c.frame_win_id)
+            .collect()
     }
 
     pub fn get_repositioned_children(&self) -> Vec<&Container> {
@@ -136,7 +514,294 @@ impl Workspace {
         self.container.get_removed_children()
     }
 
-    pub(crate) fn change_layout(&self, layout_type: LayoutType) {
-        todo!()
+    /// Change the layout of the focused split, or of the split holding the focused window.
+    pub(crate) fn change_layout(&mut self, layout_type: LayoutType) {
+        let split = self.get_focused_split();
+        unsafe { (*split).set_layout_type(layout_type) };
+        self.container.reposition();
+    }
+
+    /// Put the focused container in a new split laid out with `layout_type`, where the
+    /// next windows open. A container alone in its split changes the layout of the split.
+    pub(crate) fn split(&mut self, layout_type: LayoutType) {
+        let Some((focused, _)) = self.resolve_focus() else {
+            return;
+        };
+        unsafe {
+            if (*focused).is_floating() {
+                return;
+            }
+            let parent = &mut *(*focused).get_parent();
+            if parent.len() == 1 && parent.is_child() {
+                parent.set_layout_type(layout_type);
+            } else {
+                let index = parent.position_of(focused).unwrap();
+                let container = parent.take_child(index);
+                let (x, y) = container.get_position();
+                let (width, height) = container.get_dimensions();
+                let mut split =
+                    Container::new_without_window(layout_type, Geometry::new(x, y, width, height));
+                split.add_child(container);
+                parent.insert_child(index, split);
+            }
+        }
+        self.container.relink();
+        self.container.reposition();
+    }
+
+    pub(crate) fn toggle_layout(&mut self) {
+        let split = self.get_focused_split();
+        let layout_type = unsafe { (*split).layout_type() }.toggled();
+        self.change_layout(layout_type);
+    }
+
+    fn get_focused_split(&mut self) -> *mut Container {
+        match self.resolve_focus() {
+            Some((focused, _)) if unsafe { (*focused).is_leaf() } => unsafe {
+                (*focused).get_parent()
+            },
+            Some((focused, _)) => focused,
+            None => &mut self.container as *mut Container,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Workspaces are built in place by the tests: children point at the root container,
+    // so a workspace holding windows must not move.
+
+    /// Add the windows in order, each next to the last.
+    fn add_windows(workspace: &mut Workspace, windows: &[WindowId]) {
+        for &window in windows {
+            add_window(workspace, window);
+        }
+    }
+
+    fn add_window(workspace: &mut Workspace, window: WindowId) {
+        let container = Container::new(
+            window + 100,
+            window,
+            LayoutType::Horizontal,
+            Geometry::new(0, 0, 0, 0),
+        );
+        workspace.add_container(container);
+    }
+
+    fn geometry(workspace: &Workspace, window: WindowId) -> (u32, u32, u32, u32) {
+        let leaves = workspace.container.leaves();
+        let container = leaves
+            .iter()
+            .find(|c| c.main_win_id == Some(window))
+            .unwrap();
+        let ((x, y), (width, height)) = (container.get_position(), container.get_dimensions());
+        (x, y, width, height)
+    }
+
+    fn focused_windows(workspace: &Workspace) -> Vec<WindowId> {
+        workspace
+            .get_current_focused_container()
+            .leaves()
+            .iter()
+            .filter_map(|c| c.main_win_id)
+            .collect()
+    }
+
+    #[test]
+    fn split_opens_the_next_window_next_to_the_focused_one() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(&mut workspace, 3);
+
+        assert_eq!(geometry(&workspace, 1), (0, 0, 500, 500));
+        assert_eq!(geometry(&workspace, 2), (500, 0, 500, 250));
+        assert_eq!(geometry(&workspace, 3), (500, 250, 500, 250));
+    }
+
+    #[test]
+    fn split_of_a_lone_container_changes_the_layout_of_its_split() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        workspace.split(LayoutType::Horizontal);
+        add_window(&mut workspace, 3);
+
+        assert_eq!(workspace.container.len(), 2);
+        assert_eq!(geometry(&workspace, 2), (500, 0, 250, 500));
+        assert_eq!(geometry(&workspace, 3), (750, 0, 250, 500));
+    }
+
+    #[test]
+    fn focus_parent_and_child_walk_the_splits() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(&mut workspace, 3);
+
+        workspace.focus_parent();
+        assert_eq!(focused_windows(&workspace), vec![2, 3]);
+        // the workspace itself is never focused
+        workspace.focus_parent();
+        assert_eq!(focused_windows(&workspace), vec![2, 3]);
+        workspace.focus_child();
+        assert_eq!(focused_windows(&workspace), vec![3]);
+    }
+
+    #[test]
+    fn layout_of_a_focused_split_changes() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(&mut workspace, 3);
+        workspace.focus_parent();
+        workspace.change_layout(LayoutType::Horizontal);
+
+        assert_eq!(geometry(&workspace, 1), (0, 0, 500, 500));
+        assert_eq!(geometry(&workspace, 2), (500, 0, 250, 500));
+        assert_eq!(geometry(&workspace, 3), (750, 0, 250, 500));
+    }
+
+    #[test]
+    fn focused_split_is_taken_whole() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(&mut workspace, 3);
+        workspace.focus_parent();
+
+        let taken = workspace.take_focused_container(&[3, 2, 1]).unwrap();
+        let taken: Vec<WindowId> = taken
+            .leaves()
+            .iter()
+            .filter_map(|c| c.main_win_id)
+            .collect();
+        assert_eq!(taken, vec![2, 3]);
+        assert_eq!(workspace.get_focused_window(), Some(1));
+        assert_eq!(geometry(&workspace, 1), (0, 0, 1000, 500));
+    }
+
+    #[test]
+    fn floating_focused_split_keeps_its_windows_together() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(&mut workspace, 3);
+        workspace.focus_parent();
+        workspace.set_floating(true, &[3, 2, 1]);
+
+        assert!(workspace.is_focused_floating());
+        assert_eq!(focused_windows(&workspace), vec![2, 3]);
+        assert_eq!(geometry(&workspace, 1), (0, 0, 1000, 500));
+    }
+
+    /// Window 1 on the left, 2 above 3 in a split on the right, 3 focused.
+    fn add_windows_with_split(workspace: &mut Workspace) {
+        add_windows(workspace, &[1, 2]);
+        workspace.split(LayoutType::Vertical);
+        add_window(workspace, 3);
+    }
+
+    #[test]
+    fn focus_in_direction_crosses_split_boundaries() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        assert_eq!(
+            workspace.find_window_in_direction(&[], Direction::Left, false),
+            Some(1)
+        );
+        assert_eq!(
+            workspace.find_window_in_direction(&[], Direction::Up, false),
+            Some(2)
+        );
+        workspace.set_current_focused_container(2);
+        assert_eq!(
+            workspace.find_window_in_direction(&[], Direction::Down, false),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn focus_in_direction_wraps_at_the_edge_only_when_asked() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        assert_eq!(
+            workspace.find_window_in_direction(&[], Direction::Right, false),
+            None
+        );
+        assert_eq!(
+            workspace.find_window_in_direction(&[], Direction::Right, true),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn focus_in_direction_reaches_other_workspaces() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        let mut other = Workspace::new(800, 500);
+        other.set_area(1000, 0, 800, 500);
+        add_window(&mut other, 4);
+
+        assert_eq!(
+            workspace.find_window_in_direction(&[&other], Direction::Right, false),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn move_swaps_with_a_sibling() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        assert!(workspace.move_container(Direction::Left));
+
+        assert_eq!(geometry(&workspace, 2), (0, 0, 500, 500));
+        assert_eq!(geometry(&workspace, 1), (500, 0, 500, 500));
+    }
+
+    #[test]
+    fn move_enters_the_neighbouring_split() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        workspace.set_current_focused_container(1);
+        assert!(workspace.move_container(Direction::Right));
+
+        assert_eq!(geometry(&workspace, 1), (0, 0, 1000, 166));
+        assert_eq!(geometry(&workspace, 2), (0, 166, 1000, 166));
+        assert_eq!(geometry(&workspace, 3), (0, 332, 1000, 166));
+    }
+
+    #[test]
+    fn move_leaves_its_split_at_the_edge() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        assert!(workspace.move_container(Direction::Left));
+
+        assert_eq!(geometry(&workspace, 1), (0, 0, 333, 500));
+        assert_eq!(geometry(&workspace, 3), (333, 0, 333, 500));
+        assert_eq!(geometry(&workspace, 2), (666, 0, 333, 500));
+    }
+
+    #[test]
+    fn move_stops_at_the_edge_of_the_workspace() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows_with_split(&mut workspace);
+        assert!(!workspace.move_container(Direction::Down));
+
+        assert_eq!(geometry(&workspace, 3), (500, 250, 500, 250));
+    }
+
+    #[test]
+    fn floating_containers_follow_the_area_of_the_workspace() {
+        let mut workspace = Workspace::new(1000, 500);
+        add_windows(&mut workspace, &[1, 2]);
+        workspace.set_floating(true, &[2, 1]);
+        workspace.move_floating(100, 50);
+        workspace.set_area(1000, 0, 800, 500);
+
+        assert_eq!(geometry(&workspace, 1), (1000, 0, 800, 500));
+        assert_eq!(geometry(&workspace, 2), (1100, 50, 500, 500));
     }
 }
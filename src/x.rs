@@ -6,6 +6,24 @@ pub enum Error {
     #[error("Unknown error: {0}")]
     Unknown(String),
 
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+
+    #[error("Unknown mode: {0}")]
+    UnknownMode(String),
+
+    #[error("Invalid arguments for {0}: {1}")]
+    InvalidArguments(String, String),
+
+    #[error("Invalid config: {0}")]
+    Config(String),
+
+    #[error("Cannot run {0}: {1}")]
+    Spawn(String, String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
     #[error(transparent)]
     X11rbConnect(#[from] ConnectError),
 
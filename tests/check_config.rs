@@ -0,0 +1,21 @@
+use std::process::Command;
+
+fn check_config(path: &str) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_lazywm"))
+        .args(["--check-config", "-c", path])
+        .output()
+        .expect("lazywm runs")
+}
+
+#[test]
+fn shipped_configs_have_no_problems() {
+    for path in ["examples/config.yaml", "src/default_config.yaml"] {
+        let output = check_config(path);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}